@@ -3,16 +3,32 @@ fn main() {
         .join("test_crate")
         .join("Cargo.toml");
     println!("Begin watching for changes to {:?}", test_crate_path);
-    let watch = hotlib::watch(&test_crate_path).unwrap();
+    let watch = hotlib::watch(&test_crate_path).unwrap().abi_versions(1..=1);
     let mut lib = watch.package().build().unwrap().load().unwrap();
     loop {
-        unsafe {
-            let foo: libloading::Symbol<fn(i32, i32) -> i32> = lib.get(b"foo").unwrap();
-            let res = foo(6, 7);
-            println!("{}", res);
+        // Checked against the signature exported via
+        // `#[hotlib_plugin::export]`, so changing `foo`'s
+        // signature produces an error, not a crash.
+        match lib.get_checked::<fn(i32, i32) -> i32>("foo") {
+            Ok(foo_fn) => println!("{}", foo_fn(6, 7)),
+            Err(err) => println!("{}", err),
         }
         println!("Awaiting next change...");
         let pkg = watch.next().unwrap();
-        lib = pkg.build().unwrap().load().unwrap();
+
+        // Keep the previous library in service if the
+        // new one fails to build or load, e.g. as it
+        // declares an incompatible ABI version.
+        let build = match pkg.build() {
+            Ok(build) => build,
+            Err(err) => {
+                println!("{}", err);
+                continue;
+            }
+        };
+        match build.load() {
+            Ok(new_lib) => lib = new_lib,
+            Err(err) => println!("{}, keeping the previous library", err),
+        }
     }
 }
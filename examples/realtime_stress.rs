@@ -0,0 +1,68 @@
+//! Stress tests `RealtimeLibrary` by continuously
+//! calling into the library from a "real-time"
+//! thread while the main thread reloads it.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+// The table of functions resolved from each
+// generation of the library.
+struct Table {
+    foo: fn(i32, i32) -> i32,
+}
+
+const RELOADS: usize = 200;
+
+fn main() {
+    let test_crate_path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("test_crate")
+        .join("Cargo.toml");
+    let watch = hotlib::watch(&test_crate_path).unwrap();
+    let build = watch.package().build().unwrap();
+
+    let load = || {
+        let lib = build.load().unwrap();
+        let foo_fn = unsafe { *lib.get::<fn(i32, i32) -> i32>(b"foo").unwrap() };
+        (lib, Table { foo: foo_fn })
+    };
+
+    let (lib, table) = load();
+    let mut rt_lib = hotlib::RealtimeLibrary::new(lib, table);
+    let mut reader = rt_lib.reader();
+    let expected = (reader.read().foo)(6, 7);
+
+    // Call into the library continuously, checking
+    // that generations only ever advance.
+    let stop = Arc::new(AtomicBool::new(false));
+    let rt_stop = stop.clone();
+    let rt_thread = std::thread::spawn(move || {
+        let mut calls = 0u64;
+        let mut last = 0;
+        while !rt_stop.load(Ordering::Relaxed) {
+            let (generation, table) = reader.read_generation();
+            assert!(generation >= last, "generation went backwards");
+            assert_eq!((table.foo)(6, 7), expected);
+            last = generation;
+            calls += 1;
+        }
+        (reader, calls)
+    });
+
+    for _ in 0..RELOADS {
+        let (lib, table) = load();
+        rt_lib.swap(lib, table);
+    }
+    stop.store(true, Ordering::Relaxed);
+    let (reader, calls) = rt_thread.join().unwrap();
+    drop(reader);
+
+    let remaining = rt_lib.collect();
+    println!(
+        "{} reloads, {} calls, {} retired generations remaining, {} leaked",
+        RELOADS,
+        calls,
+        remaining,
+        hotlib::leaked_generations(),
+    );
+    assert_eq!(remaining, 0);
+}
@@ -0,0 +1,268 @@
+//! Macros for generating a typed interface to a
+//! hot-loaded library.
+//!
+//! A trait describing the library's API is shared
+//! between the library and the host:
+//!
+//! - `#[interface]` on the trait generates a
+//!   `{Trait}Table` struct of function pointers
+//!   that the host loads in one call via
+//!   `TempLibrary::table`.
+//! - `#[export]` on the library's implementation of
+//!   the trait generates a `#[no_mangle]` function
+//!   for each of its methods.
+//!
+//! `#[export]` may also be applied to a single
+//! function, which may then be retrieved via
+//! `TempLibrary::get_checked`.
+//!
+//! These are re-exported by `hotlib-plugin`, which
+//! the generated code refers to, so both the
+//! library and the crate defining the trait should
+//! depend on `hotlib-plugin`.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::spanned::Spanned;
+
+/// Generate a `{Trait}Table` struct holding a
+/// function pointer for each function of the
+/// trait, loadable from a `TempLibrary`.
+///
+/// Each function must be an associated function
+/// without a receiver, generics or `async`, as it
+/// is called via a plain function pointer.
+#[proc_macro_attribute]
+pub fn interface(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let item_trait = syn::parse_macro_input!(item as syn::ItemTrait);
+    let result = no_args(attr).and_then(|()| interface_table(&item_trait));
+    match result {
+        Ok(table) => quote!(#item_trait #table).into(),
+        Err(err) => {
+            let err = err.to_compile_error();
+            quote!(#item_trait #err).into()
+        }
+    }
+}
+
+/// Export a function, or each function of a trait
+/// implementation, as a `#[no_mangle]` function of
+/// the same name.
+///
+/// A `__hotlib_signature_{name}` function is also
+/// exported for each, returning a hash of the
+/// function's signature for checking via
+/// `TempLibrary::get_checked`.
+///
+/// When applied to an `impl Trait for Type` block,
+/// `Trait` should be an `#[interface]` trait.
+#[proc_macro_attribute]
+pub fn export(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let item = syn::parse_macro_input!(item as syn::Item);
+    let result = no_args(attr).and_then(|()| match &item {
+        syn::Item::Fn(item_fn) => export_fn(item_fn),
+        syn::Item::Impl(item_impl) => export_impl(item_impl),
+        item => {
+            let msg = "`#[export]` must be applied to a function or an `impl Trait for Type` block";
+            Err(syn::Error::new(item.span(), msg))
+        }
+    });
+    match result {
+        Ok(tokens) => tokens.into(),
+        Err(err) => {
+            let err = err.to_compile_error();
+            quote!(#item #err).into()
+        }
+    }
+}
+
+// Neither attribute accepts arguments.
+fn no_args(attr: TokenStream) -> syn::Result<()> {
+    let attr = TokenStream2::from(attr);
+    if attr.is_empty() {
+        return Ok(());
+    }
+    Err(syn::Error::new(attr.span(), "this attribute does not accept arguments"))
+}
+
+// Generate the table struct and its
+// `SymbolTable` implementation.
+fn interface_table(item_trait: &syn::ItemTrait) -> syn::Result<TokenStream2> {
+    if !item_trait.generics.params.is_empty() {
+        let msg = "`#[interface]` traits may not be generic";
+        return Err(syn::Error::new(item_trait.generics.span(), msg));
+    }
+
+    let vis = &item_trait.vis;
+    let table = format_ident!("{}Table", item_trait.ident);
+    let doc = format!("The functions of a `{}` library, as loaded via `TempLibrary::table`.", item_trait.ident);
+    let mut fields = vec![];
+    let mut names = vec![];
+    let mut tys = vec![];
+    for item in &item_trait.items {
+        let sig = match item {
+            syn::TraitItem::Fn(item) => &item.sig,
+            item => {
+                let msg = "`#[interface]` traits may only contain functions";
+                return Err(syn::Error::new(item.span(), msg));
+            }
+        };
+        check_sig(sig)?;
+        let name = &sig.ident;
+        let ty = fn_ptr(sig);
+        fields.push(quote!(pub #name: #ty));
+        names.push(name);
+        tys.push(ty);
+    }
+    let symbols = names.iter().map(|name| name.to_string());
+    // Prefixed so as not to shadow the loader.
+    let locals: Vec<_> = names.iter().map(|name| format_ident!("__{}", name)).collect();
+
+    Ok(quote! {
+        #[doc = #doc]
+        #[derive(Clone, Copy, Debug)]
+        #vis struct #table {
+            #(#fields,)*
+        }
+
+        unsafe impl ::hotlib_plugin::SymbolTable for #table {
+            fn load(
+                symbols: &dyn ::hotlib_plugin::Symbols,
+            ) -> ::std::result::Result<Self, ::hotlib_plugin::TableError> {
+                let mut loader = ::hotlib_plugin::__private::TableLoader::new(symbols);
+                #(let #locals = unsafe { loader.get::<#tys>(#symbols) };)*
+                loader.finish()?;
+                ::std::result::Result::Ok(#table {
+                    #(#names: #locals.expect("invalid symbols are reported by `finish`"),)*
+                })
+            }
+        }
+    })
+}
+
+// Export the function along with its signature
+// hash.
+fn export_fn(item_fn: &syn::ItemFn) -> syn::Result<TokenStream2> {
+    check_sig(&item_fn.sig)?;
+    let signature = signature_fn(&item_fn.sig);
+    Ok(quote! {
+        #[unsafe(no_mangle)]
+        #item_fn
+
+        const _: () = {
+            #signature
+        };
+    })
+}
+
+// Export a function forwarding to each function of
+// the implementation, along with its signature
+// hash.
+fn export_impl(item_impl: &syn::ItemImpl) -> syn::Result<TokenStream2> {
+    let trait_path = match &item_impl.trait_ {
+        Some((None, path, _)) => path,
+        _ => {
+            let msg = "`#[export]` must be applied to an `impl Trait for Type` block";
+            return Err(syn::Error::new(item_impl.span(), msg));
+        }
+    };
+    if !item_impl.generics.params.is_empty() {
+        let msg = "`#[export]` implementations may not be generic";
+        return Err(syn::Error::new(item_impl.generics.span(), msg));
+    }
+
+    let self_ty = &item_impl.self_ty;
+    let mut fns = vec![];
+    for item in &item_impl.items {
+        let sig = match item {
+            syn::ImplItem::Fn(item) => &item.sig,
+            _ => continue,
+        };
+        check_sig(sig)?;
+        let name = &sig.ident;
+        let unsafety = &sig.unsafety;
+        let abi = &sig.abi;
+        let output = &sig.output;
+        let args: Vec<_> = (0..sig.inputs.len()).map(|i| format_ident!("arg{}", i)).collect();
+        let tys = sig.inputs.iter().map(|input| match input {
+            syn::FnArg::Typed(pat) => &pat.ty,
+            syn::FnArg::Receiver(_) => unreachable!("receivers are rejected by `check_sig`"),
+        });
+        let signature = signature_fn(sig);
+        fns.push(quote! {
+            #[unsafe(no_mangle)]
+            pub #unsafety #abi fn #name(#(#args: #tys),*) #output {
+                <#self_ty as #trait_path>::#name(#(#args),*)
+            }
+
+            #signature
+        });
+    }
+
+    // Exported within an anonymous constant so that
+    // the functions do not clash with other items.
+    Ok(quote! {
+        #item_impl
+
+        const _: () = {
+            #(#fns)*
+        };
+    })
+}
+
+// The function exporting the signature hash of the
+// function with the given signature.
+fn signature_fn(sig: &syn::Signature) -> TokenStream2 {
+    let name = format_ident!("__hotlib_signature_{}", sig.ident);
+    let ty = fn_ptr(sig);
+    quote! {
+        #[unsafe(no_mangle)]
+        pub extern "C" fn #name() -> u64 {
+            ::hotlib_plugin::signature_hash::<#ty>()
+        }
+    }
+}
+
+// Check that the function may be called via a
+// plain function pointer.
+fn check_sig(sig: &syn::Signature) -> syn::Result<()> {
+    if let Some(receiver) = sig.receiver() {
+        let msg = "interface functions may not take `self`";
+        return Err(syn::Error::new(receiver.span(), msg));
+    }
+    if !sig.generics.params.is_empty() {
+        let msg = "interface functions may not be generic";
+        return Err(syn::Error::new(sig.generics.span(), msg));
+    }
+    if let Some(asyncness) = sig.asyncness {
+        let msg = "interface functions may not be `async`";
+        return Err(syn::Error::new(asyncness.span(), msg));
+    }
+    if let Some(variadic) = &sig.variadic {
+        let msg = "interface functions may not be variadic";
+        return Err(syn::Error::new(variadic.span(), msg));
+    }
+    let impl_trait = sig.inputs.iter().any(|input| match input {
+        syn::FnArg::Typed(pat) => matches!(*pat.ty, syn::Type::ImplTrait(_)),
+        syn::FnArg::Receiver(_) => false,
+    });
+    if impl_trait {
+        let msg = "interface functions may not take `impl Trait` arguments";
+        return Err(syn::Error::new(sig.inputs.span(), msg));
+    }
+    Ok(())
+}
+
+// The function pointer type of the given
+// signature.
+fn fn_ptr(sig: &syn::Signature) -> TokenStream2 {
+    let unsafety = &sig.unsafety;
+    let abi = &sig.abi;
+    let output = &sig.output;
+    let tys = sig.inputs.iter().filter_map(|input| match input {
+        syn::FnArg::Typed(pat) => Some(&pat.ty),
+        syn::FnArg::Receiver(_) => None,
+    });
+    quote!(#unsafety #abi fn(#(#tys),*) #output)
+}
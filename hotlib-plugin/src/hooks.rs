@@ -0,0 +1,37 @@
+//! The context passed to the optional lifecycle
+//! hooks exported by a library.
+
+/// Passed to a library's `hotlib_on_load` and
+/// `hotlib_on_unload` hooks.
+///
+/// A library may export either hook in order to
+/// start and stop its worker threads, flush
+/// caches, etc:
+///
+/// ```
+/// #[no_mangle]
+/// pub extern "C" fn hotlib_on_load(ctx: &hotlib_plugin::HookContext) {}
+///
+/// #[no_mangle]
+/// pub extern "C" fn hotlib_on_unload(ctx: &hotlib_plugin::HookContext) {}
+/// ```
+///
+/// `hotlib_on_load` is called once the library is
+/// loaded and its ABI version accepted, before
+/// `Loader::load` returns. `hotlib_on_unload` is
+/// called just before the library is closed.
+/// Anything that would keep the library mapped
+/// (e.g. running threads) should be stopped by
+/// then. See `Loader::hooks`.
+///
+/// Fields may be appended in later versions, so
+/// libraries should only access the context by
+/// reference.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HookContext {
+    /// The number of libraries previously loaded
+    /// from the same path by this process, i.e. `0`
+    /// upon the initial load.
+    pub reloads: u64,
+}
@@ -0,0 +1,30 @@
+//! The types shared between hot-loaded libraries
+//! and the `hotlib` host that loads them.
+//!
+//! Libraries depend on this crate rather than on
+//! `hotlib` itself, so that they do not link the
+//! host's file watching and loading machinery. The
+//! host accesses the same types via their
+//! re-exports from `hotlib`.
+
+mod hooks;
+mod services;
+mod signature;
+mod state;
+mod table;
+
+pub use hooks::HookContext;
+pub use services::HostServices;
+pub use signature::signature_hash;
+pub use state::{StateRef, StateWriter};
+pub use table::{SymbolTable, Symbols, TableError};
+#[cfg(feature = "macros")]
+pub use hotlib_macros::{export, interface};
+
+// Items used by code generated via `hotlib-macros`
+// and by `hotlib`.
+#[doc(hidden)]
+pub mod __private {
+    pub use crate::signature::PREFIX as SIGNATURE_PREFIX;
+    pub use crate::table::TableLoader;
+}
@@ -0,0 +1,92 @@
+//! The table of host callbacks (logging, asset
+//! lookup, spawning tasks, etc) passed to each
+//! loaded library, so that libraries may call back
+//! into the host without linking against it.
+
+use std::os::raw::c_void;
+
+/// A versioned table of host callbacks, passed to
+/// each newly loaded library via its `hotlib_init`
+/// function.
+///
+/// The table itself is a `#[repr(C)]` struct
+/// defined by the host, typically of `extern "C"`
+/// function pointers, and registered via
+/// `hotlib::set_host_services`:
+///
+/// ```
+/// #[repr(C)]
+/// pub struct Host {
+///     pub log: extern "C" fn(*const u8, usize),
+/// }
+///
+/// #[no_mangle]
+/// pub extern "C" fn hotlib_init(services: &hotlib_plugin::HostServices) {
+///     if services.version() == 1 {
+///         if let Some(host) = unsafe { services.table::<Host>() } {
+///             let msg = "initialised";
+///             (host.log)(msg.as_ptr(), msg.len());
+///         }
+///     }
+/// }
+/// ```
+///
+/// `hotlib_init` is called once the library is
+/// loaded and its ABI version accepted, before its
+/// `hotlib_on_load` hook. See `Loader::hooks`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct HostServices {
+    version: u32,
+    size:    usize,
+    table:   *const c_void,
+}
+
+// The table is `Sync` and lives for the life of
+// the process.
+unsafe impl Send for HostServices {}
+unsafe impl Sync for HostServices {}
+
+impl HostServices {
+
+    /// Describe the table registered by the host.
+    ///
+    /// Used by `hotlib::set_host_services`.
+    #[doc(hidden)]
+    pub fn new<T: Sync>(version: u32, table: &'static T) -> Self {
+        HostServices {
+            version,
+            size: std::mem::size_of::<T>(),
+            table: table as *const T as *const c_void,
+        }
+    }
+
+    /// The version of the table, as registered by
+    /// the host.
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// The size of the table in bytes.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// The table of host callbacks.
+    ///
+    /// Returns `None` if the table is smaller than
+    /// `T`, e.g. as the host was built against an
+    /// older version of the table.
+    ///
+    /// # Safety
+    ///
+    /// `T` must be a `#[repr(C)]` prefix of the table
+    /// registered by the host, e.g. the same type as
+    /// of the same `version`.
+    pub unsafe fn table<T>(&self) -> Option<&'static T> {
+        if self.size < std::mem::size_of::<T>() {
+            return None;
+        }
+        Some(&*(self.table as *const T))
+    }
+}
@@ -0,0 +1,50 @@
+//! Hashing the signatures of exported functions, so
+//! that the host may check them before use.
+//!
+//! `#[export]` exports a `__hotlib_signature_{name}`
+//! function for each exported function, returning
+//! a hash of the function's type name. Type names
+//! identify types by path only and may change
+//! between compiler versions, so the library and
+//! host should share their types via a common crate
+//! and be built with the same compiler.
+
+/// The prefix of the symbol exporting the signature
+/// hash of the symbol with the given name.
+pub const PREFIX: &str = "__hotlib_signature_";
+
+/// A stable hash of the signature of `T`.
+///
+/// Uses 64-bit FNV-1a over the type name, as the
+/// standard library's hashers may change between
+/// releases.
+pub fn signature_hash<T: ?Sized>() -> u64 {
+    const OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+    std::any::type_name::<T>()
+        .bytes()
+        .fold(OFFSET, |hash, byte| (hash ^ byte as u64).wrapping_mul(PRIME))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hash_is_fnv1a_of_the_type_name() {
+        // The hash must not change between releases,
+        // as libraries and hosts may be built against
+        // different versions of this crate.
+        assert_eq!(signature_hash::<i32>(), 0x2af2_b319_2b41_9145);
+    }
+
+    #[test]
+    fn hash_distinguishes_signatures() {
+        let a = signature_hash::<fn(i32, i32) -> i32>();
+        let b = signature_hash::<fn(i64, i64) -> i64>();
+        let c = signature_hash::<extern "C" fn(i32, i32) -> i32>();
+        assert_eq!(a, signature_hash::<fn(i32, i32) -> i32>());
+        assert_ne!(a, b);
+        assert_ne!(a, c);
+    }
+}
@@ -0,0 +1,131 @@
+//! The types via which a library saves and restores
+//! its in-memory state across reloads.
+//!
+//! Before a generation is replaced, its
+//! `hotlib_save_state` function is asked to write
+//! its state into a buffer owned by the host. Once
+//! the next generation is loaded, the buffer is
+//! passed to its `hotlib_load_state` function. The
+//! state is tagged with a version, so that the
+//! next generation may migrate or discard state
+//! saved by an incompatible build.
+
+use std::os::raw::c_void;
+
+/// Passed to a library's `hotlib_save_state`
+/// function in order to save its state:
+///
+/// ```
+/// #[no_mangle]
+/// pub extern "C" fn hotlib_save_state(state: &mut hotlib_plugin::StateWriter) {
+///     state.write(1, b"state");
+/// }
+/// ```
+///
+/// The bytes are copied into a buffer allocated by
+/// the host, so they remain valid once the library
+/// is unloaded.
+#[repr(C)]
+pub struct StateWriter {
+    state: *mut c_void,
+    write: extern "C" fn(*mut c_void, u32, *const u8, usize),
+}
+
+/// Passed to a library's `hotlib_load_state`
+/// function with the state saved by the previous
+/// generation:
+///
+/// ```
+/// #[no_mangle]
+/// pub extern "C" fn hotlib_load_state(state: &hotlib_plugin::StateRef) {
+///     if state.version() == 1 {
+///         let _bytes = state.bytes();
+///     }
+/// }
+/// ```
+///
+/// The state is only valid for the duration of the
+/// call.
+#[repr(C)]
+pub struct StateRef {
+    version: u32,
+    data:    *const u8,
+    len:     usize,
+}
+
+impl StateWriter {
+
+    /// A writer passing each write to the given
+    /// callback along with `state`.
+    ///
+    /// Used by `hotlib::TempLibrary::save_state`.
+    ///
+    /// # Safety
+    ///
+    /// `write` must accept `state` for as long as the
+    /// writer is in use.
+    #[doc(hidden)]
+    pub unsafe fn new(state: *mut c_void, write: extern "C" fn(*mut c_void, u32, *const u8, usize)) -> Self {
+        StateWriter { state, write }
+    }
+
+    /// Save the given bytes with the given version,
+    /// replacing any previously written state.
+    pub fn write(&mut self, version: u32, bytes: &[u8]) {
+        (self.write)(self.state, version, bytes.as_ptr(), bytes.len());
+    }
+
+    /// Save the given value serialized as JSON with
+    /// the given version.
+    #[cfg(feature = "serde")]
+    pub fn serialize<T>(&mut self, version: u32, value: &T) -> Result<(), serde_json::Error>
+    where
+        T: ?Sized + serde::Serialize,
+    {
+        let bytes = serde_json::to_vec(value)?;
+        self.write(version, &bytes);
+        Ok(())
+    }
+}
+
+impl StateRef {
+
+    /// Refer to the given saved bytes.
+    ///
+    /// Used by `hotlib::TempLibrary::restore_state`.
+    ///
+    /// # Safety
+    ///
+    /// The bytes must outlive the `StateRef`.
+    #[doc(hidden)]
+    pub unsafe fn new(version: u32, bytes: &[u8]) -> Self {
+        StateRef {
+            version,
+            data: bytes.as_ptr(),
+            len:  bytes.len(),
+        }
+    }
+
+    /// The version with which the state was saved.
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// The saved bytes.
+    pub fn bytes(&self) -> &[u8] {
+        if self.len == 0 {
+            return &[];
+        }
+        unsafe { std::slice::from_raw_parts(self.data, self.len) }
+    }
+
+    /// Deserialize a value saved via
+    /// `StateWriter::serialize`.
+    #[cfg(feature = "serde")]
+    pub fn deserialize<T>(&self) -> Result<T, serde_json::Error>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        serde_json::from_slice(self.bytes())
+    }
+}
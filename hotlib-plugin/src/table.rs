@@ -0,0 +1,122 @@
+//! Loading a whole table of symbols from a library
+//! in one call, failing the load if any of them is
+//! missing or has the wrong signature.
+
+use crate::signature::{signature_hash, PREFIX};
+use std::os::raw::c_void;
+
+/// A source of symbols from which a `SymbolTable`
+/// may be loaded.
+///
+/// Implemented by `hotlib::TempLibrary`.
+pub trait Symbols {
+    /// The address of the symbol with the given
+    /// name, if the library exports it.
+    fn symbol(&self, name: &str) -> Option<*const c_void>;
+}
+
+/// A table of symbols (typically function pointers)
+/// that may be loaded from a library via
+/// `hotlib::TempLibrary::table`.
+///
+/// Usually generated by the `#[interface]`
+/// attribute.
+///
+/// # Safety
+///
+/// The type of each symbol within the table must
+/// match the type of the symbol within the
+/// library, as with `libloading::Library::get`.
+pub unsafe trait SymbolTable: Sized {
+    /// Resolve every symbol of the table from the
+    /// given library.
+    fn load(symbols: &dyn Symbols) -> Result<Self, TableError>;
+}
+
+/// The symbols of a table that could not be loaded.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TableError {
+    /// The names of the symbols that the library
+    /// does not export.
+    pub missing:    Vec<String>,
+    /// The names of the symbols whose exported
+    /// signature hash differs from their type
+    /// within the table.
+    pub mismatched: Vec<String>,
+}
+
+/// Resolves the symbols of a table, collecting the
+/// names of those that are missing or mismatched.
+#[doc(hidden)]
+pub struct TableLoader<'a> {
+    symbols: &'a dyn Symbols,
+    error:   TableError,
+}
+
+impl<'a> TableLoader<'a> {
+
+    /// Begin resolving symbols from the given
+    /// library.
+    pub fn new(symbols: &'a dyn Symbols) -> Self {
+        TableLoader { symbols, error: TableError::default() }
+    }
+
+    /// Resolve the symbol with the given name,
+    /// recording it as missing if it could not be
+    /// found.
+    ///
+    /// If the library also exports the symbol's
+    /// signature hash (as `#[export]` does), the
+    /// symbol is recorded as mismatched unless the
+    /// hash matches `T`.
+    ///
+    /// # Safety
+    ///
+    /// `T` must be pointer-sized and the type of the
+    /// symbol, as with `libloading::Library::get`.
+    pub unsafe fn get<T: Copy>(&mut self, name: &str) -> Option<T> {
+        assert_eq!(std::mem::size_of::<T>(), std::mem::size_of::<*const c_void>());
+        let symbol = match self.symbols.symbol(name) {
+            Some(symbol) => symbol,
+            None => {
+                self.error.missing.push(name.to_string());
+                return None;
+            }
+        };
+        let signature_name = format!("{}{}", PREFIX, name);
+        if let Some(signature) = self.symbols.symbol(&signature_name) {
+            let signature: extern "C" fn() -> u64 = std::mem::transmute(signature);
+            if signature() != signature_hash::<T>() {
+                self.error.mismatched.push(name.to_string());
+                return None;
+            }
+        }
+        Some(std::mem::transmute_copy(&symbol))
+    }
+
+    /// Produce an error naming every missing or
+    /// mismatched symbol, if any.
+    pub fn finish(self) -> Result<(), TableError> {
+        if self.error.missing.is_empty() && self.error.mismatched.is_empty() {
+            return Ok(());
+        }
+        Err(self.error)
+    }
+}
+
+impl std::fmt::Display for TableError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if !self.missing.is_empty() {
+            write!(f, "missing symbols: {}", self.missing.join(", "))?;
+        }
+        if !self.missing.is_empty() && !self.mismatched.is_empty() {
+            write!(f, "; ")?;
+        }
+        if !self.mismatched.is_empty() {
+            write!(f, "mismatched signatures: {}", self.mismatched.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for TableError {}
@@ -0,0 +1,114 @@
+//! Loading the tables generated by `#[interface]`
+//! from the functions exported by `#[export]`.
+
+use hotlib_plugin::{export, interface, signature_hash, SymbolTable, Symbols, TableError};
+use std::collections::HashMap;
+use std::os::raw::c_void;
+
+#[interface]
+pub trait Math {
+    fn add(a: i32, b: i32) -> i32;
+    fn neg(a: i32) -> i32;
+}
+
+struct Impl;
+
+#[export]
+impl Math for Impl {
+    fn add(a: i32, b: i32) -> i32 {
+        a + b
+    }
+    fn neg(a: i32) -> i32 {
+        -a
+    }
+}
+
+#[export]
+pub fn mul(a: i32, b: i32) -> i32 {
+    a * b
+}
+
+// The exported functions, which are otherwise
+// hidden within anonymous constants.
+extern "Rust" {
+    fn add(a: i32, b: i32) -> i32;
+    fn neg(a: i32) -> i32;
+}
+extern "C" {
+    fn __hotlib_signature_add() -> u64;
+    fn __hotlib_signature_neg() -> u64;
+    fn __hotlib_signature_mul() -> u64;
+}
+
+// Symbols resolved from a map rather than from a
+// loaded library.
+#[derive(Default)]
+struct Map(HashMap<&'static str, *const c_void>);
+
+impl Symbols for Map {
+    fn symbol(&self, name: &str) -> Option<*const c_void> {
+        self.0.get(name).copied()
+    }
+}
+
+fn exports() -> Map {
+    let mut map = Map::default();
+    map.0.insert("add", add as *const c_void);
+    map.0.insert("neg", neg as *const c_void);
+    map.0.insert("__hotlib_signature_add", __hotlib_signature_add as *const c_void);
+    map.0.insert("__hotlib_signature_neg", __hotlib_signature_neg as *const c_void);
+    map
+}
+
+#[test]
+fn table_calls_exported_functions() {
+    let table = MathTable::load(&exports()).unwrap();
+    assert_eq!((table.add)(2, 3), 5);
+    assert_eq!((table.neg)(2), -2);
+}
+
+#[test]
+fn table_reports_every_missing_symbol() {
+    let err = MathTable::load(&Map::default()).unwrap_err();
+    let expected = TableError {
+        missing:    vec!["add".to_string(), "neg".to_string()],
+        mismatched: vec![],
+    };
+    assert_eq!(err, expected);
+}
+
+#[test]
+fn table_reports_mismatched_signatures() {
+    // The signature of `add` as exported by a build
+    // in which it took `i64`s.
+    extern "C" fn signature_add_i64() -> u64 {
+        signature_hash::<fn(i64, i64) -> i64>()
+    }
+    let mut exports = exports();
+    exports.0.insert("__hotlib_signature_add", signature_add_i64 as *const c_void);
+    exports.0.remove("neg");
+    let err = MathTable::load(&exports).unwrap_err();
+    let expected = TableError {
+        missing:    vec!["neg".to_string()],
+        mismatched: vec!["add".to_string()],
+    };
+    assert_eq!(err, expected);
+}
+
+#[test]
+fn table_accepts_symbols_without_signatures() {
+    let mut exports = exports();
+    exports.0.retain(|name, _| !name.starts_with("__hotlib_signature_"));
+    let table = MathTable::load(&exports).unwrap();
+    assert_eq!((table.add)(2, 3), 5);
+}
+
+#[test]
+fn exported_signatures_match_their_types() {
+    unsafe {
+        assert_eq!(__hotlib_signature_add(), signature_hash::<fn(i32, i32) -> i32>());
+        assert_eq!(__hotlib_signature_neg(), signature_hash::<fn(i32) -> i32>());
+        assert_eq!(__hotlib_signature_mul(), signature_hash::<fn(i32, i32) -> i32>());
+    }
+    assert_eq!(mul(2, 3), 6);
+}
@@ -0,0 +1,182 @@
+//! Async support for watching, building and
+//! loading, enabled via the `futures` and `tokio`
+//! features.
+
+use crate::events::Events;
+use crate::{DylibWatch, Loader, NextError, Package, Watch};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+#[cfg(feature = "tokio")]
+use crate::{Build, BuildError, LoadError, TempLibrary};
+
+/// Yields the package each time the library
+/// should be re-built, following the same rules as
+/// `Watch::next`.
+///
+/// The stream ends if the channel used to receive
+/// file system events is closed. Polling never
+/// blocks, as events are left to settle between
+/// polls.
+impl futures_core::Stream for Watch {
+    type Item = Result<Package, NextError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut watch = &*self;
+        Pin::new(&mut watch).poll_next(cx)
+    }
+}
+
+/// The same as the `Stream` implementation for
+/// `Watch`, allowing for streaming from a shared
+/// reference.
+impl futures_core::Stream for &Watch {
+    type Item = Result<Package, NextError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let watch: &Watch = *self;
+        poll_events(&watch.events, cx).map(|next| next.map(|res| res.map(|_| watch.package())))
+    }
+}
+
+/// Yields a `Loader` each time the library is
+/// replaced, following the same rules as
+/// `DylibWatch::next`.
+///
+/// The stream ends if the channel used to receive
+/// file system events is closed.
+impl futures_core::Stream for DylibWatch {
+    type Item = Result<Loader, NextError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut watch = &*self;
+        Pin::new(&mut watch).poll_next(cx)
+    }
+}
+
+/// The same as the `Stream` implementation for
+/// `DylibWatch`, allowing for streaming from a
+/// shared reference.
+impl futures_core::Stream for &DylibWatch {
+    type Item = Result<Loader, NextError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let watch: &DylibWatch = *self;
+        poll_events(&watch.events, cx).map(|next| next.map(|res| res.map(|_| watch.loader())))
+    }
+}
+
+// Poll for the next change signalled by the given
+// events.
+fn poll_events(events: &Events, cx: &mut Context<'_>) -> Poll<Option<Result<(), NextError>>> {
+
+    // Register before checking for events so that
+    // no event may slip between the two.
+    events.readiness().register(cx.waker());
+
+    match events.try_next() {
+        Ok(true) => Poll::Ready(Some(Ok(()))),
+        Ok(false) => Poll::Pending,
+        Err(NextError::ChannelClosed) => Poll::Ready(None),
+        Err(err) => Poll::Ready(Some(Err(err))),
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl Package {
+
+    /// The same as `build`, but awaits the build
+    /// process without blocking the current thread.
+    ///
+    /// If the package's `Builder` does not provide a
+    /// `process`, `Builder::build` is called on
+    /// tokio's blocking thread pool instead.
+    ///
+    /// Must be called within a tokio runtime with
+    /// IO enabled.
+    pub async fn build_async(&self) -> Result<Build, BuildError> {
+        match self.builder().process() {
+            Some(process) => {
+                let mut command = tokio::process::Command::from(process.command);
+                let output = command.output().await?;
+                let build = Build::from_output(process.dylib_path, output)?;
+                Ok(self.configure(build))
+            }
+            None => {
+                let package = self.clone();
+                spawn_blocking(move || package.build()).await
+            }
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl Build {
+
+    /// The same as `load`, but copies and loads the
+    /// library on tokio's blocking thread pool.
+    pub async fn load_async(&self) -> Result<TempLibrary, LoadError> {
+        let build = self.clone();
+        spawn_blocking(move || build.load()).await
+    }
+}
+
+// Run the given function on tokio's blocking
+// thread pool, resuming any panic that occurs.
+#[cfg(feature = "tokio")]
+async fn spawn_blocking<F, T, E>(f: F) -> Result<T, E>
+where
+    F: 'static + FnOnce() -> Result<T, E> + Send,
+    T: 'static + Send,
+    E: 'static + From<std::io::Error> + Send,
+{
+    match tokio::task::spawn_blocking(f).await {
+        Ok(result) => result,
+        Err(err) if err.is_panic() => std::panic::resume_unwind(err.into_panic()),
+        Err(err) => Err(E::from(std::io::Error::other(err))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_core::Stream;
+    use std::sync::Arc;
+    use std::task::{Wake, Waker};
+    use std::time::{Duration, Instant};
+
+    // Unparks the polling thread.
+    struct ThreadWaker(std::thread::Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    #[test]
+    fn polling_never_blocks() {
+        let dir = std::env::temp_dir().join(format!("hotlib-async-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("lib.so");
+        std::fs::write(&path, "a").unwrap();
+        let mut watch = crate::watch_dylib(&path).unwrap();
+
+        let waker = Waker::from(Arc::new(ThreadWaker(std::thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        std::fs::write(&path, "b").unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let ready = loop {
+            let start = Instant::now();
+            let poll = Pin::new(&mut watch).poll_next(&mut cx);
+            assert!(start.elapsed() < Duration::from_millis(10), "poll blocked");
+            if poll.is_ready() || Instant::now() > deadline {
+                break poll.is_ready();
+            }
+            std::thread::park_timeout(deadline - Instant::now());
+        };
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(ready);
+    }
+}
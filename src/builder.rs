@@ -0,0 +1,278 @@
+//! The backends used to build a watched library.
+
+use crate::{dylib_ext, Build, BuildError};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+/// Builds a watched library's dynamic library.
+///
+/// `CargoBuilder` is used by the `watch` function,
+/// while `CommandBuilder` may be used with
+/// `watch_with_builder` for libraries built by
+/// `make`, `cc`, `cargo xtask` and so on. Custom
+/// implementations (e.g. a fake builder for tests)
+/// need only implement `build`.
+pub trait Builder: Send + Sync {
+
+    /// Build the library, blocking until complete.
+    fn build(&self) -> Result<Build, BuildError>;
+
+    /// The process that builds the library, if the
+    /// build consists of running a single process.
+    ///
+    /// This allows async builds to await the
+    /// process without blocking a thread. By
+    /// default this is `None`, in which case async
+    /// builds call `build` on a blocking thread.
+    fn process(&self) -> Option<BuildProcess> {
+        None
+    }
+}
+
+/// A process that builds a dynamic library along
+/// with the path at which the library is expected
+/// upon successful completion.
+#[derive(Debug)]
+pub struct BuildProcess {
+    /// The command that builds the library.
+    pub command:    std::process::Command,
+    /// The path to the resulting dynamic library.
+    pub dylib_path: PathBuf,
+}
+
+/// Builds the dylib target of a cargo package via
+/// `cargo build --lib --release`.
+#[derive(Clone, Debug)]
+pub struct CargoBuilder {
+    manifest_path:   PathBuf,
+    lib_name:        String,
+    target_dir_path: PathBuf,
+}
+
+/// Runs a command, then expects the dynamic
+/// library at the given path.
+#[derive(Clone, Debug)]
+pub struct CommandBuilder {
+    program:     OsString,
+    args:        Vec<OsString>,
+    envs:        Vec<(OsString, OsString)>,
+    current_dir: Option<PathBuf>,
+    dylib_path:  PathBuf,
+}
+
+impl BuildProcess {
+
+    /// Run the process to completion and produce
+    /// the `Build`.
+    pub fn run(mut self) -> Result<Build, BuildError> {
+        let output = self.command.output()?;
+        Build::from_output(self.dylib_path, output)
+    }
+}
+
+impl CargoBuilder {
+
+    /// Build the dylib target with the given name of
+    /// the package at the given `Cargo.toml`,
+    /// expecting the library within the given target
+    /// directory.
+    ///
+    /// `watch` determines these via
+    /// `cargo metadata`.
+    pub fn new(
+        manifest_path: impl Into<PathBuf>,
+        lib_name: impl Into<String>,
+        target_dir_path: impl Into<PathBuf>,
+    ) -> Self {
+        CargoBuilder {
+            manifest_path:   manifest_path.into(),
+            lib_name:        lib_name.into(),
+            target_dir_path: target_dir_path.into(),
+        }
+    }
+
+    /// The path to the package's `Cargo.toml`.
+    pub fn manifest_path(&self) -> &Path {
+        &self.manifest_path
+    }
+
+    /// The name of the package's dylib target.
+    pub fn lib_name(&self) -> &str {
+        &self.lib_name
+    }
+
+    /// The path to the generated dylib target.
+    pub fn dylib_path(&self) -> PathBuf {
+        self.target_dir_path
+            .join("release")
+            .join(self.file_stem())
+            .with_extension(dylib_ext())
+    }
+
+    // Tell cargo to compile the package.
+    fn build_process(&self) -> BuildProcess {
+        let manifest_path_str = format!("{}", self.manifest_path.display());
+        let mut command = std::process::Command::new("cargo");
+        command
+            .arg("build")
+            .arg("--manifest-path")
+            .arg(&manifest_path_str)
+            .arg("--lib")
+            .arg("--release");
+        let dylib_path = self.dylib_path();
+        BuildProcess { command, dylib_path }
+    }
+
+    // The file stem of the built dynamic library.
+    fn file_stem(&self) -> String {
+
+        // TODO: On windows, the generated lib
+        // does not contain the "lib" prefix.
+        //
+        // A proper solution would likely involve
+        // retrieving the file stem from cargo
+        // itself.
+        #[cfg(target_os = "windows")]
+        {
+            self.lib_name.to_string()
+        }
+
+        #[cfg(not(target_os = "windows"))]
+        {
+            format!("lib{}", self.lib_name)
+        }
+    }
+}
+
+impl CommandBuilder {
+
+    /// Run the given program, then expect the
+    /// dynamic library at `dylib_path`.
+    pub fn new(program: impl Into<OsString>, dylib_path: impl Into<PathBuf>) -> Self {
+        CommandBuilder {
+            program:     program.into(),
+            args:        vec![],
+            envs:        vec![],
+            current_dir: None,
+            dylib_path:  dylib_path.into(),
+        }
+    }
+
+    /// Add an argument to pass to the program.
+    pub fn arg(mut self, arg: impl Into<OsString>) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    /// Add multiple arguments to pass to the
+    /// program.
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<OsString>,
+    {
+        self.args.extend(args.into_iter().map(Into::into));
+        self
+    }
+
+    /// Set an environment variable for the program.
+    pub fn env(mut self, key: impl Into<OsString>, val: impl Into<OsString>) -> Self {
+        self.envs.push((key.into(), val.into()));
+        self
+    }
+
+    /// Set the working directory for the program.
+    pub fn current_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.current_dir = Some(dir.into());
+        self
+    }
+
+    /// The path at which the dynamic library is
+    /// expected once the program completes.
+    pub fn dylib_path(&self) -> &Path {
+        &self.dylib_path
+    }
+
+    // Run the program with the configured args,
+    // env and working directory.
+    fn build_process(&self) -> BuildProcess {
+        let mut command = std::process::Command::new(&self.program);
+        command.args(&self.args);
+        command.envs(self.envs.iter().map(|(k, v)| (k, v)));
+        if let Some(ref dir) = self.current_dir {
+            command.current_dir(dir);
+        }
+        let dylib_path = self.dylib_path.clone();
+        BuildProcess { command, dylib_path }
+    }
+}
+
+impl Builder for CargoBuilder {
+    fn build(&self) -> Result<Build, BuildError> {
+        self.build_process().run()
+    }
+
+    fn process(&self) -> Option<BuildProcess> {
+        Some(self.build_process())
+    }
+}
+
+impl Builder for CommandBuilder {
+    fn build(&self) -> Result<Build, BuildError> {
+        self.build_process().run()
+    }
+
+    fn process(&self) -> Option<BuildProcess> {
+        Some(self.build_process())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{self, AtomicUsize};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    // A builder that counts its builds rather than
+    // running a process.
+    struct FakeBuilder {
+        builds:     Arc<AtomicUsize>,
+        dylib_path: PathBuf,
+    }
+
+    impl Builder for FakeBuilder {
+        fn build(&self) -> Result<Build, BuildError> {
+            self.builds.fetch_add(1, atomic::Ordering::SeqCst);
+            Ok(Build::new(&self.dylib_path))
+        }
+    }
+
+    #[test]
+    fn watch_with_builder_builds_via_the_given_builder() {
+        let dir = std::env::temp_dir().join(format!("hotlib-builder-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let dir = dir.canonicalize().unwrap();
+
+        let builds = Arc::new(AtomicUsize::new(0));
+        let dylib_path = dir.join("libfake.so");
+        let builder = FakeBuilder { builds: builds.clone(), dylib_path: dylib_path.clone() };
+        let watch = crate::watch_with_builder(&dir, builder).unwrap();
+        assert_eq!(watch.try_manifest_path(), None);
+
+        // A change to the source directory produces a
+        // package, which builds via the fake builder.
+        std::fs::write(dir.join("lib.c"), "int f(void) { return 1; }").unwrap();
+        let package = watch.next_timeout(Duration::from_secs(5)).unwrap().expect("no package");
+        assert_eq!(package.src_path(), dir);
+        assert_eq!(package.try_manifest_path(), None);
+        let build = package.build().unwrap();
+        assert_eq!(build.dylib_path(), dylib_path);
+        assert_eq!(builds.load(atomic::Ordering::SeqCst), 1);
+
+        watch.package().build().unwrap();
+        assert_eq!(builds.load(atomic::Ordering::SeqCst), 2);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
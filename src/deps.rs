@@ -0,0 +1,129 @@
+//! Locating the shared libraries that a dynamic
+//! library depends upon, so that they may be
+//! staged alongside its temporary copy.
+//!
+//! A library built with `-C prefer-dynamic` or
+//! against other dylib crates depends upon
+//! `libstd-*.so` and sibling `lib*.so` files that
+//! the dynamic linker would otherwise only find
+//! relative to `target/<profile>/deps` or via
+//! `LD_LIBRARY_PATH`. As the dynamic linker
+//! matches `DT_NEEDED` entries against the sonames
+//! of libraries that are already loaded, loading
+//! staged copies of these dependencies first makes
+//! them resolvable for the temporary copy.
+
+use crate::object::Object;
+use std::path::{Path, PathBuf};
+
+/// The `DT_NEEDED` entries of a dynamic library
+/// along with the directories in which they are
+/// searched for.
+pub(crate) struct Needed {
+    pub(crate) names: Vec<String>,
+    dirs:             Vec<PathBuf>,
+}
+
+impl Needed {
+
+    /// Read the `DT_NEEDED` entries of the given
+    /// library at the given path.
+    ///
+    /// Returns `None` if the library is not an ELF
+    /// shared object.
+    #[cfg(target_os = "linux")]
+    pub(crate) fn read(dylib_path: &Path, object: &Object) -> Option<Self> {
+        let elf = object.elf()?;
+        let names = elf.libraries.iter().map(|name| name.to_string()).collect();
+
+        // Entries relative to `$ORIGIN` no longer
+        // resolve from the temporary copy, while
+        // absolute entries still do.
+        let origin = dylib_path.parent().unwrap_or_else(|| Path::new("."));
+        let mut dirs: Vec<PathBuf> = elf
+            .runpaths
+            .iter()
+            .chain(&elf.rpaths)
+            .flat_map(|paths| paths.split(':'))
+            .filter(|dir| dir.contains("$ORIGIN") || dir.contains("${ORIGIN}"))
+            .map(|dir| {
+                let origin = origin.to_string_lossy();
+                PathBuf::from(dir.replace("${ORIGIN}", &origin).replace("$ORIGIN", &origin))
+            })
+            .collect();
+        dirs.push(origin.to_path_buf());
+        dirs.push(origin.join("deps"));
+        Some(Needed { names, dirs })
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub(crate) fn read(_dylib_path: &Path, _object: &Object) -> Option<Self> {
+        None
+    }
+
+    /// Locate the dependency with the given name,
+    /// returning `None` if it is already loaded or
+    /// should be left to the dynamic linker (e.g.
+    /// system libraries).
+    pub(crate) fn locate(&self, name: &str) -> Option<PathBuf> {
+        if is_loaded(name) {
+            return None;
+        }
+        self.dirs
+            .iter()
+            .map(|dir| dir.join(name))
+            .find(|path| path.is_file())
+            .or_else(|| {
+                let path = target_libdir()?.join(name);
+                path.is_file().then_some(path)
+            })
+    }
+}
+
+// Whether or not a library with the given soname
+// is already loaded into the process, in which
+// case the dynamic linker will use it.
+#[cfg(unix)]
+fn is_loaded(name: &str) -> bool {
+    let name = match std::ffi::CString::new(name) {
+        Ok(name) => name,
+        Err(_) => return false,
+    };
+    let handle = unsafe { libc::dlopen(name.as_ptr(), libc::RTLD_LAZY | libc::RTLD_NOLOAD) };
+    if handle.is_null() {
+        return false;
+    }
+    unsafe { libc::dlclose(handle) };
+    true
+}
+
+#[cfg(not(unix))]
+fn is_loaded(_name: &str) -> bool {
+    false
+}
+
+// The directory containing the Rust standard
+// library's dylibs for the host target, as
+// reported by `rustc --print target-libdir`.
+//
+// Only queried if a dependency cannot be found
+// elsewhere, and cached for the life of the
+// process.
+fn target_libdir() -> Option<&'static Path> {
+    static TARGET_LIBDIR: std::sync::OnceLock<Option<PathBuf>> = std::sync::OnceLock::new();
+    TARGET_LIBDIR
+        .get_or_init(|| {
+            let rustc = std::env::var_os("RUSTC").unwrap_or_else(|| "rustc".into());
+            let output = std::process::Command::new(rustc)
+                .arg("--print")
+                .arg("target-libdir")
+                .output()
+                .ok()?;
+            if !output.status.success() {
+                return None;
+            }
+            let dir = String::from_utf8(output.stdout).ok()?;
+            Some(PathBuf::from(dir.trim()))
+        })
+        .as_deref()
+}
@@ -0,0 +1,156 @@
+//! Watching a prebuilt dynamic library that is
+//! built outside of hotlib.
+
+use crate::events::Events;
+use crate::{Loader, NextError, Staging, WatchError, WatchEvent};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// Watches a prebuilt dynamic library and yields a
+/// `Loader` each time the library is replaced.
+///
+/// Created via the `watch_dylib` function.
+pub struct DylibWatch {
+    dylib_path:        PathBuf,
+    pub(crate) events: Events,
+    staging:           Staging,
+    abi_versions:      Option<RangeInclusive<u32>>,
+}
+
+/// Watch the prebuilt dynamic library at the given
+/// `Path`.
+///
+/// This is useful for libraries that are built by
+/// some other build system (e.g. make or bazel)
+/// rather than by `cargo`.
+///
+/// The library's parent directory is watched, so
+/// the library may be replaced in place or via an
+/// atomic rename. A `Loader` is only yielded once
+/// the library has been completely written (i.e.
+/// its size and modification time have settled)
+/// and its contents differ from the previously
+/// yielded library.
+pub fn watch_dylib(path: &Path) -> Result<DylibWatch, WatchError> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| WatchError::InvalidDylibPath { path: path.to_path_buf() })?;
+    let dir = match path.parent() {
+        Some(dir) if dir != Path::new("") => dir,
+        _ => Path::new("."),
+    };
+    let dir = dir
+        .canonicalize()
+        .map_err(|err| WatchError::ResolveDylibDir { path: path.to_path_buf(), err })?;
+    let dylib_path = dir.join(file_name);
+    let events = Events::file(&dylib_path)?;
+    let staging = Staging::default();
+    let abi_versions = None;
+    Ok(DylibWatch { dylib_path, events, staging, abi_versions })
+}
+
+impl DylibWatch {
+
+    /// The path to the dynamic library being
+    /// watched.
+    pub fn dylib_path(&self) -> &Path {
+        &self.dylib_path
+    }
+
+    /// Specify how the library is staged before it
+    /// is loaded by the yielded `Loader`s.
+    ///
+    /// By default this is `Staging::Copy`.
+    pub fn staging(mut self, staging: Staging) -> Self {
+        self.staging = staging;
+        self
+    }
+
+    /// Specify the ABI versions that the library may
+    /// declare when loaded by the yielded `Loader`s.
+    ///
+    /// See `Loader::abi_versions`.
+    pub fn abi_versions(mut self, versions: RangeInclusive<u32>) -> Self {
+        self.abi_versions = Some(versions);
+        self
+    }
+
+    /// Wait for the library to be replaced.
+    pub fn next(&self) -> Result<Loader, NextError> {
+        self.events.next()?;
+        Ok(self.loader())
+    }
+
+    /// The same as `next`, but returns immediately
+    /// rather than waiting for a change.
+    ///
+    /// Events that have not yet settled are checked
+    /// by a later call, and the readiness handle is
+    /// signalled once they are due.
+    pub fn try_next(&self) -> Result<Option<Loader>, NextError> {
+        Ok(self.events.try_next()?.then(|| self.loader()))
+    }
+
+    /// The same as `next`, but returns `None` if
+    /// the library is not replaced within the given
+    /// duration.
+    pub fn next_timeout(&self, timeout: Duration) -> Result<Option<Loader>, NextError> {
+        match Instant::now().checked_add(timeout) {
+            Some(deadline) => self.next_deadline(deadline),
+            None => self.next().map(Some),
+        }
+    }
+
+    /// The same as `next`, but returns `None` if
+    /// the library is not replaced before the given
+    /// deadline.
+    pub fn next_deadline(&self, deadline: Instant) -> Result<Option<Loader>, NextError> {
+        Ok(self.events.next_deadline(deadline)?.then(|| self.loader()))
+    }
+
+    /// The channel on which raw file system events
+    /// are received.
+    ///
+    /// See `Watch::receiver`.
+    pub fn receiver(&self) -> &crossbeam_channel::Receiver<WatchEvent> {
+        self.events.receiver()
+    }
+
+    /// Check an event received via `receiver`,
+    /// returning a `Loader` if the event should
+    /// trigger a reload.
+    pub fn handle_event(&self, event: WatchEvent) -> Result<Option<Loader>, NextError> {
+        Ok(self.events.handle_event(event)?.then(|| self.loader()))
+    }
+
+    /// Cause the next call to `next` or `try_next`
+    /// to yield a `Loader` immediately, whether or
+    /// not the library has changed.
+    pub fn force_reload(&self) {
+        self.events.force();
+    }
+
+    /// Manually retrieve a `Loader` for the library
+    /// immediately without checking for file
+    /// events.
+    ///
+    /// This is useful for the initial load.
+    pub fn loader(&self) -> Loader {
+        let loader = Loader::new(&self.dylib_path).staging(self.staging);
+        match self.abi_versions {
+            Some(ref versions) => loader.abi_versions(versions.clone()),
+            None => loader,
+        }
+    }
+}
+
+/// A readiness handle for event loop integration.
+///
+/// See the `AsRawFd` implementation for `Watch`.
+#[cfg(target_os = "linux")]
+impl std::os::unix::io::AsRawFd for DylibWatch {
+    fn as_raw_fd(&self) -> std::os::unix::io::RawFd {
+        self.events.readiness().fd()
+    }
+}
@@ -0,0 +1,663 @@
+//! Receiving and filtering the file system events
+//! that trigger a rebuild or reload, shared by
+//! `Watch` and `DylibWatch`.
+
+use crate::readiness::Readiness;
+use crate::{NextError, WatchError, WatchEvent};
+use notify::EventHandler;
+use notify::Watcher as NotifyWatcher;
+use std::collections::HashMap;
+use std::hash::Hasher;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{self, AtomicBool};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// Watches a path for events that change the
+/// contents of the files beneath it.
+pub(crate) struct Events {
+    _watcher:  notify::RecommendedWatcher,
+    event_rx:  crossbeam_channel::Receiver<WatchEvent>,
+    state:     Mutex<State>,
+    force:     AtomicBool,
+    readiness: Arc<Readiness>,
+    timer:     Timer,
+    file:      Option<PathBuf>,
+}
+
+// The hashes of the watched files along with the
+// changes that have not yet been checked.
+struct State {
+    content: ContentHashes,
+    pending: Pending,
+}
+
+// Changes that have been received but not yet
+// checked, as the burst of events producing them
+// may not have settled.
+#[derive(Default)]
+struct Pending {
+    paths:     Vec<PathBuf>,
+    // The moment at which the changes are
+    // considered settled.
+    settle_at: Option<Instant>,
+    // When watching a single file, the moment at
+    // which it was last sampled along with the
+    // sample.
+    sample:    Option<(Instant, FileSample)>,
+}
+
+// The size and modification time of a file, used
+// to determine whether it has been completely
+// written.
+type FileSample = (u64, Option<std::time::SystemTime>);
+
+// Wakes callers waiting on the `Events` once
+// pending changes are due to be checked, by
+// queuing an event that does not signal a change
+// and signalling the readiness handle.
+struct Timer {
+    shared: Arc<TimerShared>,
+    thread: Option<std::thread::JoinHandle<()>>,
+}
+
+struct TimerShared {
+    state:   Mutex<TimerState>,
+    condvar: Condvar,
+}
+
+#[derive(Default)]
+struct TimerState {
+    at:   Option<Instant>,
+    stop: bool,
+}
+
+// A hash of the contents of every watched file,
+// used to ignore events that leave file contents
+// unchanged.
+#[derive(Default)]
+struct ContentHashes {
+    files: HashMap<PathBuf, u64>,
+}
+
+// The duration to wait for further events before
+// hashing the contents of changed files.
+const SETTLE_DURATION: Duration = Duration::from_millis(20);
+
+// The interval for which the size and modification
+// time of a single watched file must not change
+// before it is considered completely written.
+pub(crate) const STABLE_INTERVAL: Duration = Duration::from_millis(50);
+
+//------------------------[these are for `recommended_watcher`]
+type ChannelMessage   = WatchEvent;
+type ChannelSendError =  crossbeam_channel::SendError<ChannelMessage>;
+
+struct ChannelSender(crossbeam_channel::Sender<ChannelMessage>, Arc<Readiness>);
+
+impl ChannelSender {
+    pub fn send(&mut self, msg: ChannelMessage) -> Result<(), ChannelSendError> {
+        self.0.send(msg)
+    }
+}
+
+impl EventHandler for ChannelSender {
+    fn handle_event(&mut self, event: WatchEvent) {
+        let qualifies = match event {
+            Ok(ref event) => check_raw_event(event).unwrap_or(true),
+            Err(_) => true,
+        };
+        if self.send(event).is_ok() && qualifies {
+            self.1.signal();
+        }
+    }
+}
+
+// Clears the readiness handle while events are
+// being drained, re-signalling it on `Drop` if any
+// events remain queued.
+struct ReadinessGuard<'a> {
+    events: &'a Events,
+}
+
+impl<'a> ReadinessGuard<'a> {
+    fn new(events: &'a Events) -> Self {
+        events.readiness.clear();
+        ReadinessGuard { events }
+    }
+}
+
+impl Drop for ReadinessGuard<'_> {
+    fn drop(&mut self) {
+        let events = self.events;
+        if !events.event_rx.is_empty() || events.force.load(atomic::Ordering::SeqCst) {
+            events.readiness.signal();
+        }
+    }
+}
+
+impl Events {
+
+    /// Begin watching the directory at the given
+    /// path recursively.
+    pub(crate) fn dir(path: &Path) -> Result<Self, WatchError> {
+        let mut content = ContentHashes::default();
+        content.insert_dir(path);
+        Self::new(path, notify::RecursiveMode::Recursive, content, None)
+    }
+
+    /// Begin watching the single file at the given
+    /// path, which must be canonical.
+    ///
+    /// The file's parent directory is watched so
+    /// that the file may be atomically replaced.
+    pub(crate) fn file(path: &Path) -> Result<Self, WatchError> {
+        let dir = path
+            .parent()
+            .ok_or_else(|| WatchError::InvalidDylibPath { path: path.to_path_buf() })?;
+        let mut content = ContentHashes::default();
+        if let Ok(hash) = hash_file(path) {
+            content.files.insert(path.to_path_buf(), hash);
+        }
+        let file = Some(path.to_path_buf());
+        Self::new(dir, notify::RecursiveMode::NonRecursive, content, file)
+    }
+
+    fn new(
+        path: &Path,
+        mode: notify::RecursiveMode,
+        content: ContentHashes,
+        file: Option<PathBuf>,
+    ) -> Result<Self, WatchError> {
+        let (tx, event_rx) = crossbeam_channel::unbounded();
+
+        let readiness = Readiness::new().map_err(|err| WatchError::Readiness { err })?;
+        let readiness = Arc::new(readiness);
+
+        let timer = Timer::spawn(tx.clone(), readiness.clone()).map_err(|err| WatchError::Timer { err })?;
+        let sender = ChannelSender(tx, readiness.clone());
+
+        let mut watcher = notify::recommended_watcher(sender)?;
+        watcher.watch(path, mode)?;
+
+        let state = State {
+            content,
+            pending: Pending::default(),
+        };
+        Ok(Events {
+            _watcher: watcher,
+            event_rx,
+            state:    Mutex::new(state),
+            force:    AtomicBool::new(false),
+            readiness,
+            timer,
+            file,
+        })
+    }
+
+    /// Block until some change occurs.
+    pub(crate) fn next(&self) -> Result<(), NextError> {
+        let _readiness = ReadinessGuard::new(self);
+        loop {
+            if self.take_force() || self.check()? {
+                return Ok(());
+            }
+
+            // Pending changes wake us via the timer once
+            // they are due to be checked.
+            let event = match self.event_rx.recv() {
+                Err(_) => return Err(NextError::ChannelClosed),
+                Ok(event) => event,
+            };
+            self.queue(event?)?;
+        }
+    }
+
+    /// Whether or not any of the pending events
+    /// signal a change.
+    ///
+    /// This never blocks. Changes that have not yet
+    /// settled are checked by a later call, and the
+    /// readiness handle is signalled once they are
+    /// due.
+    pub(crate) fn try_next(&self) -> Result<bool, NextError> {
+        let _readiness = ReadinessGuard::new(self);
+        Ok(self.take_force() || self.check()?)
+    }
+
+    /// Block until some change occurs or the given
+    /// deadline passes.
+    pub(crate) fn next_deadline(&self, deadline: Instant) -> Result<bool, NextError> {
+        let _readiness = ReadinessGuard::new(self);
+        loop {
+            if self.take_force() || self.check()? {
+                return Ok(true);
+            }
+
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            let event = match self.event_rx.recv_timeout(timeout) {
+                Err(crossbeam_channel::RecvTimeoutError::Timeout) => return Ok(false),
+                Err(crossbeam_channel::RecvTimeoutError::Disconnected) => {
+                    return Err(NextError::ChannelClosed)
+                }
+                Ok(event) => event,
+            };
+            self.queue(event?)?;
+        }
+    }
+
+    /// Whether or not an event received via
+    /// `receiver` signals a change.
+    ///
+    /// The same as `try_next`, this never blocks.
+    pub(crate) fn handle_event(&self, event: WatchEvent) -> Result<bool, NextError> {
+        let _readiness = ReadinessGuard::new(self);
+        if self.take_force() {
+            return Ok(true);
+        }
+        self.queue(event?)?;
+        self.check()
+    }
+
+    /// Cause the next check to signal a change.
+    pub(crate) fn force(&self) {
+        self.force.store(true, atomic::Ordering::SeqCst);
+        self.readiness.signal();
+    }
+
+    /// The channel on which raw events are received.
+    pub(crate) fn receiver(&self) -> &crossbeam_channel::Receiver<WatchEvent> {
+        &self.event_rx
+    }
+
+    /// The handle signalled whenever an event is
+    /// queued.
+    pub(crate) fn readiness(&self) -> &Readiness {
+        &self.readiness
+    }
+
+    // Consume a pending `force` request.
+    fn take_force(&self) -> bool {
+        self.force.swap(false, atomic::Ordering::SeqCst)
+    }
+
+    // Add the given event to the pending changes.
+    fn queue(&self, event: notify::Event) -> Result<(), NextError> {
+        let mut state = self.state.lock().expect("events state lock poisoned");
+        state.pending.queue(event)
+    }
+
+    // Whether or not the queued events signal a
+    // change, taking file contents into account.
+    //
+    // Events are checked together once no further
+    // events have arrived for `SETTLE_DURATION`, so
+    // that a burst of events produced by a single
+    // write (e.g. truncate followed by write) is only
+    // hashed once the write has settled. This never
+    // waits. Until the events have settled, the timer
+    // is scheduled to wake any waiting caller once
+    // they are due.
+    fn check(&self) -> Result<bool, NextError> {
+        let mut state = self.state.lock().expect("events state lock poisoned");
+        for event in self.event_rx.try_iter() {
+            state.pending.queue(event?)?;
+        }
+        let settle_at = match state.pending.settle_at {
+            None => return Ok(false),
+            Some(settle_at) => settle_at,
+        };
+        if Instant::now() < settle_at {
+            self.timer.schedule(settle_at);
+            return Ok(false);
+        }
+
+        // When watching a single file, only consider
+        // it once it has been completely written.
+        if let Some(ref file) = self.file {
+            let stable_at = match state.pending.check_stable(file) {
+                None => {
+                    state.pending = Pending::default();
+                    return Ok(false);
+                }
+                Some(stable_at) => stable_at,
+            };
+            if Instant::now() < stable_at {
+                self.timer.schedule(stable_at);
+                return Ok(false);
+            }
+            state.pending = Pending::default();
+            return Ok(state.content.update(std::slice::from_ref(file)));
+        }
+
+        let paths = std::mem::take(&mut state.pending).paths;
+        Ok(state.content.update(&paths))
+    }
+}
+
+impl Pending {
+
+    // Add the paths of the given event if it may
+    // signal a change, restarting the settle period.
+    fn queue(&mut self, event: notify::Event) -> Result<(), NextError> {
+        if check_raw_event(&event)? {
+            for path in event.paths {
+                if !self.paths.contains(&path) {
+                    self.paths.push(path);
+                }
+            }
+            self.settle_at = Some(Instant::now() + SETTLE_DURATION);
+        }
+        Ok(())
+    }
+
+    // Sample the given file, returning the moment at
+    // which it will be considered completely written
+    // if its sample does not change in the meantime.
+    //
+    // Returns `None` if the file has not changed or
+    // no longer exists.
+    fn check_stable(&mut self, file: &Path) -> Option<Instant> {
+        if !self.paths.iter().any(|path| path == file) {
+            return None;
+        }
+        let metadata = file.metadata().ok()?;
+        let sample = (metadata.len(), metadata.modified().ok());
+        match self.sample {
+            Some((at, last)) if last == sample => Some(at + STABLE_INTERVAL),
+            _ => {
+                let now = Instant::now();
+                self.sample = Some((now, sample));
+                Some(now + STABLE_INTERVAL)
+            }
+        }
+    }
+}
+
+impl Timer {
+
+    // Spawn the thread that fires the timer.
+    fn spawn(
+        tx: crossbeam_channel::Sender<WatchEvent>,
+        readiness: Arc<Readiness>,
+    ) -> std::io::Result<Self> {
+        let shared = Arc::new(TimerShared {
+            state:   Mutex::new(TimerState::default()),
+            condvar: Condvar::new(),
+        });
+        let thread_shared = shared.clone();
+        let thread = std::thread::Builder::new()
+            .name("hotlib-events".to_string())
+            .spawn(move || thread_shared.run(&tx, &readiness))?;
+        Ok(Timer { shared, thread: Some(thread) })
+    }
+
+    // Fire the timer at the given moment, unless it
+    // is already due to fire before then.
+    fn schedule(&self, at: Instant) {
+        let mut state = self.shared.state.lock().expect("timer lock poisoned");
+        if state.at.is_none_or(|scheduled| at < scheduled) {
+            state.at = Some(at);
+            self.shared.condvar.notify_one();
+        }
+    }
+}
+
+impl TimerShared {
+
+    // Wait for each scheduled moment, then wake any
+    // waiting callers.
+    fn run(&self, tx: &crossbeam_channel::Sender<WatchEvent>, readiness: &Readiness) {
+        let mut state = self.state.lock().expect("timer lock poisoned");
+        while !state.stop {
+            let now = Instant::now();
+            state = match state.at {
+                None => self.condvar.wait(state).expect("timer lock poisoned"),
+                Some(at) if now < at => {
+                    self.condvar
+                        .wait_timeout(state, at - now)
+                        .expect("timer lock poisoned")
+                        .0
+                }
+                Some(_) => {
+                    state.at = None;
+                    let wake = notify::Event::new(notify::EventKind::Other);
+                    if tx.send(Ok(wake)).is_err() {
+                        return;
+                    }
+                    readiness.signal();
+                    state
+                }
+            };
+        }
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        self.shared.state.lock().expect("timer lock poisoned").stop = true;
+        self.shared.condvar.notify_one();
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+    }
+}
+
+impl ContentHashes {
+
+    // Hash every file within the given directory.
+    fn insert_dir(&mut self, dir: &Path) {
+        let entries = match std::fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(_) => return,
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                self.insert_dir(&path);
+            } else if let Ok(hash) = hash_file(&path) {
+                self.files.insert(path, hash);
+            }
+        }
+    }
+
+    // Re-hash the given paths, returning whether
+    // or not the contents of any of them changed.
+    fn update(&mut self, paths: &[PathBuf]) -> bool {
+        let mut changed = false;
+        for path in paths {
+            if path.is_dir() {
+                let mut dir = ContentHashes::default();
+                dir.insert_dir(path);
+                for (path, hash) in dir.files {
+                    changed |= self.files.insert(path, hash) != Some(hash);
+                }
+                continue;
+            }
+            match hash_file(path) {
+                Ok(hash) => changed |= self.files.insert(path.clone(), hash) != Some(hash),
+                Err(_) => {
+                    // The file no longer exists (or is a
+                    // removed directory), so forget it and
+                    // everything beneath it.
+                    let before = self.files.len();
+                    self.files.retain(|p, _| !p.starts_with(path));
+                    changed |= self.files.len() != before;
+                }
+            }
+        }
+        changed
+    }
+}
+
+// Whether or not the given event should trigger
+// a rebuild.
+fn check_raw_event(event: &notify::Event) -> Result<bool, NextError> {
+
+    use notify::event::*;
+
+    let kind = &event.kind;
+
+    let close_write = matches!(
+        event.kind,
+        EventKind::Access(AccessKind::Close(AccessMode::Write))
+    );
+
+    Ok(
+        kind.is_create()
+        || kind.is_remove()
+        || kind.is_modify()
+        || close_write
+    )
+}
+
+// Produce a hash of the contents of the file at
+// the given path.
+pub(crate) fn hash_file(path: &Path) -> std::io::Result<u64> {
+    let file = std::fs::File::open(path)?;
+    let mut hasher = HashWriter::new(std::io::sink());
+    std::io::copy(&mut std::io::BufReader::new(file), &mut hasher)?;
+    Ok(hasher.finish())
+}
+
+// Produce a hash of the given contents, matching
+// `hash_file` for a file with these contents.
+pub(crate) fn hash_bytes(bytes: &[u8]) -> u64 {
+    let mut hasher = HashWriter::new(std::io::sink());
+    std::io::Write::write_all(&mut hasher, bytes).expect("writing to a sink never fails");
+    hasher.finish()
+}
+
+/// A writer that hashes all bytes written to it
+/// before forwarding them to the inner writer.
+pub(crate) struct HashWriter<W> {
+    inner:  W,
+    hasher: std::collections::hash_map::DefaultHasher,
+}
+
+impl<W> HashWriter<W> {
+    pub(crate) fn new(inner: W) -> Self {
+        let hasher = Default::default();
+        HashWriter { inner, hasher }
+    }
+
+    /// The hash of all bytes written so far.
+    pub(crate) fn finish(&self) -> u64 {
+        self.hasher.finish()
+    }
+
+    pub(crate) fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: std::io::Write> std::io::Write for HashWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.write(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A new empty directory for the test with the
+    // given name.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("hotlib-events-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir.canonicalize().unwrap()
+    }
+
+    #[test]
+    fn next_deadline_returns_while_events_keep_arriving() {
+        let dir = test_dir("deadline");
+        let events = Events::dir(&dir).unwrap();
+        let stop = Arc::new(AtomicBool::new(false));
+        let writer = {
+            let (dir, stop) = (dir.clone(), stop.clone());
+            std::thread::spawn(move || {
+                let mut n = 0u64;
+                while !stop.load(atomic::Ordering::SeqCst) {
+                    std::fs::write(dir.join("file"), n.to_string()).unwrap();
+                    n += 1;
+                    std::thread::sleep(Duration::from_millis(5));
+                }
+            })
+        };
+        std::thread::sleep(Duration::from_millis(50));
+
+        let start = Instant::now();
+        let timeout = Duration::from_millis(100);
+        events.next_deadline(start + timeout).unwrap();
+        let elapsed = start.elapsed();
+
+        stop.store(true, atomic::Ordering::SeqCst);
+        writer.join().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(elapsed < timeout * 2, "returned after {:?}", elapsed);
+    }
+
+    #[test]
+    fn file_is_checked_once_stable_without_blocking() {
+        let dir = test_dir("stable");
+        let file = dir.join("file");
+        std::fs::write(&file, "a").unwrap();
+        let events = Events::file(&file).unwrap();
+
+        std::fs::write(&file, "b").unwrap();
+        std::thread::sleep(SETTLE_DURATION * 2);
+        let start = Instant::now();
+        assert!(!events.try_next().unwrap());
+        assert!(start.elapsed() < STABLE_INTERVAL / 2);
+
+        let changed = events.next_deadline(Instant::now() + Duration::from_secs(5)).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(changed);
+    }
+
+    #[test]
+    fn content_hashes_only_report_changed_contents() {
+        let dir = test_dir("content");
+        let (a, sub) = (dir.join("a"), dir.join("sub"));
+        let b = sub.join("b");
+        std::fs::create_dir(&sub).unwrap();
+        std::fs::write(&a, "a").unwrap();
+        std::fs::write(&b, "b").unwrap();
+        let mut content = ContentHashes::default();
+        content.insert_dir(&dir);
+
+        // Rewriting the same contents is not a change.
+        std::fs::write(&a, "a").unwrap();
+        let unchanged = content.update(std::slice::from_ref(&a));
+        std::fs::write(&a, "A").unwrap();
+        let modified = content.update(std::slice::from_ref(&a));
+        let repeated = content.update(std::slice::from_ref(&a));
+
+        // Files beneath new directories are hashed.
+        let c = dir.join("new").join("c");
+        std::fs::create_dir(c.parent().unwrap()).unwrap();
+        std::fs::write(&c, "c").unwrap();
+        let created = content.update(&[c.parent().unwrap().to_path_buf()]);
+
+        // Removing a directory forgets its files.
+        std::fs::remove_dir_all(&sub).unwrap();
+        let removed = content.update(std::slice::from_ref(&sub));
+        let forgotten = !content.files.contains_key(&b);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(!unchanged);
+        assert!(modified);
+        assert!(!repeated);
+        assert!(created);
+        assert!(removed);
+        assert!(forgotten);
+    }
+}
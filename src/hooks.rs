@@ -0,0 +1,80 @@
+//! Calling the optional lifecycle hooks exported
+//! by a library.
+
+use crate::HookContext;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{self, AtomicBool};
+use std::sync::{Mutex, OnceLock};
+
+// The type of both hooks.
+type Hook = extern "C" fn(&HookContext);
+
+/// The context for the next library loaded from the
+/// given path.
+pub(crate) fn next_context(dylib_path: &Path) -> HookContext {
+    static LOADS: OnceLock<Mutex<HashMap<PathBuf, u64>>> = OnceLock::new();
+    let mut loads = LOADS
+        .get_or_init(Default::default)
+        .lock()
+        .expect("loads lock poisoned");
+    let count = loads.entry(dylib_path.to_path_buf()).or_insert(0);
+    let reloads = *count;
+    *count += 1;
+    HookContext { reloads }
+}
+
+/// The hooks of a loaded library, recording
+/// whether or not `hotlib_on_load` has been called.
+#[derive(Debug)]
+pub(crate) struct Hooks {
+    ctx:    HookContext,
+    loaded: AtomicBool,
+}
+
+impl Hooks {
+    pub(crate) fn new(ctx: HookContext) -> Self {
+        let loaded = AtomicBool::new(false);
+        Hooks { ctx, loaded }
+    }
+
+    /// Call the library's `hotlib_on_load` hook
+    /// unless it has already been called.
+    pub(crate) fn load(&self, lib: &libloading::Library) {
+        if !self.loaded.swap(true, atomic::Ordering::SeqCst) {
+            on_load(lib, &self.ctx);
+        }
+    }
+
+    /// Call the library's `hotlib_on_unload` hook if
+    /// its `hotlib_on_load` hook was called.
+    pub(crate) fn unload(&self, lib: &libloading::Library) {
+        if self.loaded.load(atomic::Ordering::SeqCst) {
+            on_unload(lib, &self.ctx);
+        }
+    }
+}
+
+/// Call the library's `hotlib_on_load` hook, if it
+/// exports one.
+fn on_load(lib: &libloading::Library, ctx: &HookContext) {
+    call(lib, b"hotlib_on_load\0", ctx);
+}
+
+/// Call the library's `hotlib_on_unload` hook, if
+/// it exports one.
+fn on_unload(lib: &libloading::Library, ctx: &HookContext) {
+    call(lib, b"hotlib_on_unload\0", ctx);
+}
+
+// Call the hook with the given nul-terminated
+// name, if the library exports it.
+fn call(lib: &libloading::Library, name: &[u8], ctx: &HookContext) {
+    let hook = match unsafe { lib.get::<Hook>(name) } {
+        Ok(hook) => *hook,
+        Err(_) => return,
+    };
+    let name = String::from_utf8_lossy(&name[..name.len() - 1]);
+    tracing::debug!("calling {} with {:?}", name, ctx);
+    hook(ctx);
+}
@@ -0,0 +1,415 @@
+//! A shared handle to a hot-reloaded library that
+//! may be safely swapped while in use by other
+//! threads.
+
+use crate::{LoadError, Loader, SymbolError, TempLibrary};
+use std::cell::Cell;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::SystemTime;
+
+/// A shared handle to the current generation of a
+/// hot-reloaded library.
+///
+/// Replacing a `TempLibrary` while another thread
+/// is executing its code is unsound. Instead, each
+/// reload via `swap` creates a new `Generation`,
+/// while readers pin the generation they are using
+/// via `current`. A previous generation is only
+/// unloaded once every guard on it is released.
+///
+/// The handle is cheap to clone and may be shared
+/// between threads.
+#[derive(Clone)]
+pub struct HotLibrary {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    current: RwLock<Arc<Generation>>,
+    // Held while replacing the current generation,
+    // so that each new generation receives the
+    // state of the generation it replaces.
+    install: Mutex<()>,
+}
+
+/// A single loaded generation of a `HotLibrary`.
+#[derive(Debug)]
+pub struct Generation {
+    number: u64,
+    lib:    TempLibrary,
+}
+
+/// Pins a generation of a `HotLibrary`, keeping it
+/// loaded until the guard is dropped.
+///
+/// Symbols retrieved via the guard borrow from it,
+/// so they may not outlive the generation.
+#[derive(Clone, Debug)]
+pub struct GenerationGuard {
+    generation: Arc<Generation>,
+}
+
+/// A symbol that is resolved from whichever
+/// generation of a `HotLibrary` is current.
+///
+/// Created once via `HotLibrary::symbol`. The
+/// resolved value is cached per generation, so
+/// the symbol is only looked up again once a new
+/// generation is swapped in.
+///
+/// The cache is not shared between threads, so
+/// each thread should use its own clone.
+#[derive(Clone)]
+pub struct HotSymbol<T: Copy> {
+    hot:   HotLibrary,
+    name:  Vec<u8>,
+    // The generation the value was resolved from.
+    cache: Cell<Option<(u64, T)>>,
+}
+
+/// A value resolved by a `HotSymbol`, pinning the
+/// generation it was resolved from.
+pub struct HotSymbolGuard<T> {
+    generation: GenerationGuard,
+    value:      T,
+}
+
+impl HotLibrary {
+
+    /// Create a handle whose first generation is
+    /// the given library.
+    pub fn new(lib: TempLibrary) -> Self {
+        let generation = Generation { number: 0, lib };
+        let shared = Shared {
+            current: RwLock::new(Arc::new(generation)),
+            install: Mutex::new(()),
+        };
+        HotLibrary { shared: Arc::new(shared) }
+    }
+
+    /// Load the first generation via the given
+    /// `Loader`.
+    pub fn load(loader: &Loader) -> Result<Self, LoadError> {
+        loader.load().map(Self::new)
+    }
+
+    /// Pin the current generation.
+    ///
+    /// This is cheap, only briefly acquiring a read
+    /// lock in order to clone an `Arc`.
+    pub fn current(&self) -> GenerationGuard {
+        let current = self.shared.current.read().expect("current generation lock poisoned");
+        let generation = current.clone();
+        GenerationGuard { generation }
+    }
+
+    /// The number of the current generation.
+    pub fn generation(&self) -> u64 {
+        self.current().number()
+    }
+
+    /// Replace the current generation with the given
+    /// library, returning the new generation's
+    /// number.
+    ///
+    /// The state of the current generation is first
+    /// transferred to the new library via
+    /// `TempLibrary::transfer_state`. Unless the
+    /// library was loaded via
+    /// `Loader::defer_on_load`, its `hotlib_on_load`
+    /// hook has already run before its state is
+    /// restored. Prefer `reload`, which restores the
+    /// state first.
+    ///
+    /// The previous generation is unloaded once all
+    /// guards on it are released, which may happen
+    /// on whichever thread releases the last guard.
+    pub fn swap(&self, lib: TempLibrary) -> u64 {
+        let _install = self.shared.install.lock().expect("install lock poisoned");
+        self.current().transfer_state(&lib);
+        self.install(lib)
+    }
+
+    /// Load a new generation via the given `Loader`
+    /// and swap it in, returning its number.
+    ///
+    /// The state of the current generation is passed
+    /// to the new library via `Loader::state`, so
+    /// that it is restored before the library's
+    /// `hotlib_on_load` hook is called.
+    ///
+    /// The hook is deferred via
+    /// `Loader::defer_on_load` until the generation
+    /// is installed. As the previous generation's
+    /// `hotlib_on_unload` hook is only called once
+    /// its last guard is released, the two
+    /// generations' hooks may overlap.
+    pub fn reload(&self, loader: &Loader) -> Result<u64, LoadError> {
+        let _install = self.shared.install.lock().expect("install lock poisoned");
+        let loader = loader.clone().defer_on_load(true);
+        let lib = match self.current().save_state() {
+            Some(state) => loader.state(state).load()?,
+            None => loader.load()?,
+        };
+        Ok(self.install(lib))
+    }
+
+    // Replace the current generation with the given
+    // library, returning the new generation's number,
+    // then call its deferred `hotlib_on_load` hook,
+    // if any.
+    //
+    // The install lock must be held.
+    fn install(&self, lib: TempLibrary) -> u64 {
+        // The number is assigned under the lock so that
+        // concurrent swaps install generations in
+        // increasing order.
+        let (generation, previous) = {
+            let mut current = self.shared.current.write().expect("current generation lock poisoned");
+            let number = current.number + 1;
+            let generation = Arc::new(Generation { number, lib });
+            (generation.clone(), std::mem::replace(&mut *current, generation))
+        };
+        generation.lib.on_load();
+
+        // Drop the previous generation outside of the
+        // lock, as it may unload the library.
+        std::mem::drop(previous);
+        generation.number
+    }
+
+    /// Register the symbol with the given name,
+    /// which is resolved from the current
+    /// generation upon each call to
+    /// `HotSymbol::get`.
+    ///
+    /// # Safety
+    ///
+    /// `T` must be the type of the symbol within
+    /// every generation of the library, as with
+    /// `libloading::Library::get`.
+    pub unsafe fn symbol<T: Copy>(&self, name: &[u8]) -> HotSymbol<T> {
+        HotSymbol {
+            hot:   self.clone(),
+            name:  name.to_vec(),
+            cache: Cell::new(None),
+        }
+    }
+}
+
+impl<T: Copy> HotSymbol<T> {
+
+    /// Resolve the symbol from the current
+    /// generation, re-using the cached value if the
+    /// generation has not changed.
+    ///
+    /// Returns an error if the current generation
+    /// does not contain the symbol, e.g. as it was
+    /// removed or renamed in the latest build.
+    pub fn get(&self) -> Result<HotSymbolGuard<T>, SymbolError> {
+        let generation = self.hot.current();
+        let number = generation.number();
+        let cached = self.cache.get();
+        if let Some((cached_number, value)) = cached {
+            if cached_number == number {
+                return Ok(HotSymbolGuard { generation, value });
+            }
+        }
+
+        let symbol = unsafe { generation.get::<T>(&self.name) };
+        let value = match symbol {
+            Ok(symbol) => *symbol,
+            Err(err) => {
+                let name = self.name().into_owned();
+                return Err(match cached {
+                    Some((last_generation, _)) => SymbolError::Disappeared {
+                        name,
+                        generation: number,
+                        last_generation,
+                        err,
+                    },
+                    None => SymbolError::Missing {
+                        name,
+                        generation: number,
+                        err,
+                    },
+                });
+            }
+        };
+        self.cache.set(Some((number, value)));
+        Ok(HotSymbolGuard { generation, value })
+    }
+
+    /// The name of the symbol.
+    pub fn name(&self) -> std::borrow::Cow<'_, str> {
+        let name = self.name.strip_suffix(b"\0").unwrap_or(&self.name);
+        String::from_utf8_lossy(name)
+    }
+}
+
+impl<T> HotSymbolGuard<T> {
+
+    /// The generation the value was resolved from.
+    pub fn generation(&self) -> &Generation {
+        &self.generation
+    }
+}
+
+impl Generation {
+
+    /// The generation number.
+    ///
+    /// The first generation is `0`, and each swap
+    /// increments the number.
+    pub fn number(&self) -> u64 {
+        self.number
+    }
+
+    /// The time at which this generation's library
+    /// was built.
+    pub fn build_timestamp(&self) -> SystemTime {
+        self.lib.build_timestamp()
+    }
+
+    /// The loaded library.
+    pub fn lib(&self) -> &TempLibrary {
+        &self.lib
+    }
+}
+
+impl std::ops::Deref for Generation {
+    type Target = TempLibrary;
+    fn deref(&self) -> &Self::Target {
+        &self.lib
+    }
+}
+
+impl std::ops::Deref for GenerationGuard {
+    type Target = Generation;
+    fn deref(&self) -> &Self::Target {
+        &self.generation
+    }
+}
+
+impl<T> std::ops::Deref for HotSymbolGuard<T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        &self.value
+    }
+}
+
+impl<T: Copy> std::fmt::Debug for HotSymbol<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("HotSymbol")
+            .field("name", &self.name())
+            .finish_non_exhaustive()
+    }
+}
+
+impl std::fmt::Debug for HotLibrary {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("HotLibrary")
+            .field("generation", &self.generation())
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    // The path to the system library with the given
+    // file name, of which each load stages a copy.
+    fn system_lib(name: &str) -> PathBuf {
+        ["/lib/x86_64-linux-gnu", "/lib64", "/usr/lib64", "/lib", "/usr/lib"]
+            .iter()
+            .map(|dir| PathBuf::from(dir).join(name))
+            .find(|path| path.is_file())
+            .unwrap_or_else(|| panic!("failed to find {}", name))
+    }
+
+    #[test]
+    fn generations_are_numbered_in_order() {
+        let loader = Loader::new(system_lib("libm.so.6"));
+        let hot = HotLibrary::load(&loader).unwrap();
+        assert_eq!(hot.generation(), 0);
+        assert_eq!(hot.swap(loader.load().unwrap()), 1);
+        assert_eq!(hot.reload(&loader).unwrap(), 2);
+        assert_eq!(hot.generation(), 2);
+        assert_eq!(hot.current().number(), 2);
+    }
+
+    #[test]
+    fn guards_pin_their_generation() {
+        let loader = Loader::new(system_lib("libm.so.6"));
+        let hot = HotLibrary::load(&loader).unwrap();
+        let guard = hot.current();
+        let path = guard.path().to_path_buf();
+
+        // The previous generation remains loaded while
+        // the guard is alive.
+        hot.reload(&loader).unwrap();
+        assert_eq!(guard.number(), 0);
+        assert!(path.exists());
+        assert_ne!(hot.current().path(), path);
+
+        drop(guard);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn failed_reloads_keep_the_current_generation() {
+        let hot = HotLibrary::load(&Loader::new(system_lib("libm.so.6"))).unwrap();
+        let path = hot.current().path().to_path_buf();
+        let missing = Loader::new("/nonexistent/libm.so.6");
+        assert!(hot.reload(&missing).is_err());
+        assert_eq!(hot.generation(), 0);
+        assert_eq!(hot.current().path(), path);
+        assert!(path.exists());
+    }
+
+    #[test]
+    fn symbols_are_resolved_once_per_generation() {
+        let loader = Loader::new(system_lib("libm.so.6"));
+        let hot = HotLibrary::load(&loader).unwrap();
+        let cos = unsafe { hot.symbol::<extern "C" fn(f64) -> f64>(b"cos\0") };
+        let addr = unsafe { hot.symbol::<*const std::ffi::c_void>(b"cos\0") };
+        assert_eq!((*cos.get().unwrap())(0.0), 1.0);
+        let first = *addr.get().unwrap();
+        assert_eq!(*addr.get().unwrap(), first);
+
+        // Each generation is a distinct copy, so the
+        // symbol is re-resolved at a new address.
+        hot.swap(loader.load().unwrap());
+        let value = addr.get().unwrap();
+        assert_eq!(value.generation().number(), 1);
+        assert_ne!(*value, first);
+        assert_eq!(*value, *unsafe { hot.current().get::<*const std::ffi::c_void>(b"cos\0") }.unwrap());
+        assert_eq!((*cos.get().unwrap())(0.0), 1.0);
+    }
+
+    #[test]
+    fn symbols_are_missing_or_disappear() {
+        let hot = HotLibrary::load(&Loader::new(system_lib("libm.so.6"))).unwrap();
+        let missing = unsafe { hot.symbol::<*const std::ffi::c_void>(b"hotlib_missing\0") };
+        let cos = unsafe { hot.symbol::<*const std::ffi::c_void>(b"cos\0") };
+        match missing.get() {
+            Err(SymbolError::Missing { name, generation: 0, .. }) => assert_eq!(name, "hotlib_missing"),
+            Err(err) => panic!("unexpected error: {}", err),
+            Ok(_) => panic!("resolved a missing symbol"),
+        }
+        cos.get().unwrap();
+
+        // libdl defines none of libm's symbols.
+        hot.swap(Loader::new(system_lib("libdl.so.2")).load().unwrap());
+        match cos.get() {
+            Err(SymbolError::Disappeared { name, generation: 1, last_generation: 0, .. }) => {
+                assert_eq!(name, "cos")
+            }
+            Err(err) => panic!("unexpected error: {}", err),
+            Ok(_) => panic!("resolved a symbol that disappeared"),
+        }
+        assert!(matches!(missing.get(), Err(SymbolError::Missing { generation: 1, .. })));
+    }
+}
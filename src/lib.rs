@@ -4,35 +4,83 @@
 //! You are likely looking for the [watch function
 //! docs](./fn.watch.html).
 
-use notify::Watcher as NotifyWatcher;
-use notify::EventHandler;
-use slug::slugify;
+use events::{hash_bytes, hash_file, Events};
+use std::ops::RangeInclusive;
 use std::path::{Path, PathBuf};
-use std::time::SystemTime;
+use std::time::{Duration, Instant, SystemTime};
+use std::sync::Arc;
 use thiserror::Error;
 
+#[cfg(feature = "futures")]
+mod async_api;
+mod builder;
+mod deps;
+mod dylib_watch;
+mod events;
+mod hooks;
+mod hot;
+mod loader;
+mod object;
+mod readiness;
+mod realtime;
+mod services;
+mod signature;
+mod state;
+mod table;
+mod tmp;
+mod unload;
+mod validate;
+
 #[doc(inline)]
 pub use libloading::{self, Library, Symbol};
+#[doc(inline)]
+pub use crossbeam_channel;
+pub use builder::{BuildProcess, Builder, CargoBuilder, CommandBuilder};
+pub use dylib_watch::{watch_dylib, DylibWatch};
+pub use hot::{Generation, GenerationGuard, HotLibrary, HotSymbol, HotSymbolGuard};
+pub use loader::{LoadOptions, Loader, Staging};
+pub use realtime::{RealtimeLibrary, RealtimeReader};
+pub use services::set_host_services;
+pub use state::SavedState;
+pub use tmp::{cleanup, cleanup_dir, set_tmp_dir, tmp_dir};
+pub use unload::{leaked_generations, StillMappedCause, UnloadOutcome};
+#[doc(inline)]
+pub use hotlib_plugin::{
+    signature_hash, HookContext, HostServices, StateRef, StateWriter, SymbolTable, Symbols,
+    TableError,
+};
+
+/// A raw file system event, as delivered via the
+/// `Watch`'s `receiver`.
+pub type WatchEvent = Result<notify::Event, notify::Error>;
 
 /// Watches and re-builds the library upon changes
 /// to its source code.
 pub struct Watch {
-    package_info: PackageInfo,
-    _watcher:     notify::RecommendedWatcher,
-    event_rx:     crossbeam_channel::Receiver<Result<notify::Event,notify::Error>>,
+    package_info: Arc<PackageInfo>,
+    events:       Events,
+    staging:      Staging,
+    abi_versions: Option<RangeInclusive<u32>>,
 }
 
 struct PackageInfo {
-    manifest_path:   PathBuf,
-    src_path:        PathBuf,
-    lib_name:        String,
-    target_dir_path: PathBuf,
+    manifest_path: Option<PathBuf>,
+    src_path:      PathBuf,
+    builder:       Box<dyn Builder>,
 }
 
 /// The information required to build the
 /// package's dylib target.
-pub struct Package<'a> {
-    info: &'a PackageInfo,
+///
+/// The package shares its information with the
+/// `Watch` that produced it, but does not borrow
+/// from it, so it may be cloned, queued or sent
+/// to another thread to be built.
+#[derive(Clone, Debug)]
+pub struct Package {
+    info:         Arc<PackageInfo>,
+    staging:      Staging,
+    abi_versions: Option<RangeInclusive<u32>>,
 }
 
 /// The result of building a package's dynamic
@@ -44,168 +92,42 @@ pub struct Package<'a> {
 /// the library.
 #[derive(Clone)]
 pub struct Build {
-    lib_name:        String,
-    target_dir_path: PathBuf,
-    timestamp:       SystemTime,
-    output:          std::process::Output,
+    dylib_path:   PathBuf,
+    timestamp:    SystemTime,
+    output:       std::process::Output,
+    staging:      Staging,
+    options:      LoadOptions,
+    abi_versions: Option<RangeInclusive<u32>>,
 }
 
 /// A wrapper around a `libloading::Library` that
 /// cleans up the library on `Drop`.
 #[derive(Debug)]
 pub struct TempLibrary {
-    build_timestamp: SystemTime,
-    path:            PathBuf,
+    build_timestamp:  SystemTime,
+    path:             PathBuf,
+    staging:          Staging,
+    staging_duration: Duration,
+    options:          LoadOptions,
+
+    // The library's hooks, or `None` if they are
+    // not called.
+    hooks:            Option<hooks::Hooks>,
 
     // This is always `Some`. An `Option` is only
     // used so that the library may be `Drop`ped
     // during the `TempLibrary`'s `drop`
     // implementation before the temporary library
     // file at `path` is removed.
-    lib:             Option<libloading::Library>,
-}
-
-#[derive(Debug)]
-pub enum CreateTempLibraryError {
-    CouldNotLoadDirectlyFromDylib {
-        path:  PathBuf,
-        error: LoadError
-    },
-    CannotGetMetadata {
-        path:  PathBuf,
-    },
-    CannotGetFileCreationTime {
-        path:     PathBuf,
-        metadata: std::fs::Metadata,
-    },
-    LoadError {
-        error: LoadError,
-    }
-}
-
-impl TempLibrary {
-
-    //#[tracing::instrument]
-    pub fn new(dylib_path: &PathBuf, lib_name: &str) -> Result<Self,CreateTempLibraryError> {
-
-        let metadata = dylib_path.metadata().map_err(|_err| {
-            CreateTempLibraryError::CannotGetMetadata {
-                path:  dylib_path.to_path_buf(),
-            }
-        })?;
+    lib:              Option<libloading::Library>,
 
-        let build_timestamp = metadata.created().map_err(|_err| {
-            CreateTempLibraryError::CannotGetFileCreationTime {
-                path: dylib_path.to_path_buf(),
-                metadata
-            }
-        })?;
-
-        let tmp_path = Self::tmp_dylib_path(lib_name, &build_timestamp);
-        let tmp_dir  = tmp_path.parent().expect("temp dylib path has no parent");
-
-        std::fs::write("/tmp/fuckafucka", format!{"dmt at {:?}", tmp_path}).unwrap();
-
-        // If the library already exists, load it.
-        loop {
-
-            if tmp_path.exists() {
-
-                tracing::info!("creating Library from {:?}", tmp_path);
-
-                // This is some voodoo to enable
-                // reloading of dylib on mac os
-                if cfg!(target_os = "macos") {
-
-                    tracing::info!("running install_name_tool");
-
-                    let output = std::process::Command::new("install_name_tool")
-                        .current_dir(tmp_dir)
-                        .arg("-id")
-                        .arg("''")
-                        .arg(
-                            tmp_path
-                                .file_name()
-                                .expect("temp dylib path has no file name"),
-                        )
-                        .output()
-                        .expect("ls command failed to start");
-
-                        tracing::info!("install_name_tool output status: {}", output.status);
-                        tracing::info!("install_name_tool output stdout: {}", String::from_utf8(output.stdout).unwrap());
-                        tracing::info!("install_name_tool output stdout: {}", String::from_utf8(output.stderr).unwrap());
-
-                        if !output.status.success() {
-                            tracing::info!("ERROR: install_name_tool failed!");
-                        }
-                }
-
-                let lib = libloading::Library::new(dylib_path)
-                    .map(Some)
-                    .map_err(
-                        |err| CreateTempLibraryError::CouldNotLoadDirectlyFromDylib {
-                            path:  dylib_path.to_path_buf(),
-                            error: LoadError::Library { err }
-                        }
-                    )?;
-
-                return Ok(
-                    TempLibrary {
-                        build_timestamp,
-                        path: tmp_path.clone(),
-                        lib,
-                    }
-                );
-            }
-
-            // Copy the dylib to the tmp location.
-            std::fs::create_dir_all(tmp_dir)
-                .map_err(|err| 
-                    CreateTempLibraryError::LoadError {
-                        error: LoadError::Io { err }
-                    }
-                )?;
-
-            std::fs::copy(&dylib_path, &tmp_path)
-                .map_err(|err| 
-                    CreateTempLibraryError::LoadError {
-                        error: LoadError::Io { err }
-                    }
-                )?;
-        }
-    }
-
-    /// The path to the temporary dynamic library
-    /// clone that will be created upon `load`.
-    fn tmp_dylib_path(lib_name: &str, build_timestamp: &SystemTime) -> PathBuf {
-        tmp_dir()
-            .join(Self::tmp_file_stem(lib_name, build_timestamp))
-            .with_extension(dylib_ext())
-    }
-
-    fn tmp_file_stem(lib_name: &str, build_timestamp: &SystemTime) -> String {
-        let timestamp_slug = slugify(format!("{}", humantime::format_rfc3339(*build_timestamp)));
-        format!("{}-{}", Self::file_stem(lib_name), timestamp_slug)
-    }
-
-    fn file_stem(lib_name: &str) -> String {
+    // The in-memory file from which the library was
+    // loaded when staged via `Staging::Memfd`.
+    memfd:            Option<std::fs::File>,
 
-        // TODO: On windows, the generated lib
-        // does not contain the "lib" prefix.
-        //
-        // A proper solution would likely involve
-        // retrieving the file stem from cargo
-        // itself.
-        #[cfg(target_os = "windows")]
-        {
-            format!("{}", lib_name)
-        }
-
-        #[cfg(not(target_os = "windows"))]
-        {
-            format!("lib{}", lib_name)
-        }
-    }
+    // Staged dependencies of the library. Declared
+    // last so that they are dropped after `lib`.
+    dependencies:     Vec<TempLibrary>,
 }
 
 /// Errors that might occur within the `watch` function.
@@ -236,6 +158,27 @@ pub enum WatchError {
     #[error("no dylib targets were found within the given cargo package")]
     NoDylibTarget,
 
+    #[error("failed to create the `Watch`'s readiness handle: {err}")]
+    Readiness {
+        err: std::io::Error,
+    },
+
+    #[error("invalid path: expected a path to a dynamic library file, found {path:?}")]
+    InvalidDylibPath {
+        path: PathBuf,
+    },
+
+    #[error("failed to resolve the directory of the dynamic library at {path:?}: {err}")]
+    ResolveDylibDir {
+        path: PathBuf,
+        err:  std::io::Error,
+    },
+
+    #[error("failed to spawn the thread that times watch events: {err}")]
+    Timer {
+        err: std::io::Error,
+    },
+
     #[error("failed to construct `notify::RecommendedWatcher`: {err}")]
     Notify {
         #[from]
@@ -247,7 +190,7 @@ pub enum WatchError {
 /// a library instance.
 #[derive(Debug, Error)]
 pub enum BuildError {
-    #[error("an IO error occurred while attempting to invoke cargo: {err}")]
+    #[error("an IO error occurred while attempting to invoke the build process: {err}")]
     Io {
         #[from]
         err: std::io::Error,
@@ -262,7 +205,7 @@ pub enum BuildError {
 /// A process' output indicates unsuccessful
 /// completion.
 #[derive(Debug, Error)]
-#[error("cargo process exited unsuccessfully with status code: {code:?}: {stderr}")]
+#[error("process exited unsuccessfully with status code: {code:?}: {stderr}")]
 pub struct ExitStatusUnsuccessfulError {
     pub code: Option<i32>,
     pub stderr: String,
@@ -295,6 +238,117 @@ pub enum LoadError {
         #[from]
         err: libloading::Error,
     },
+    #[error("the temporary directory {path:?} is not a directory owned by and only accessible to the current user")]
+    InsecureTmpDir {
+        path: PathBuf,
+    },
+    #[error("the temporary copy {path:?} does not match the library it was copied from")]
+    HashMismatch {
+        path: PathBuf,
+    },
+    #[error("{staging:?} staging is not supported on this platform")]
+    StagingUnsupported {
+        staging: Staging,
+    },
+    #[error("`RTLD_DEEPBIND` is not supported on this platform")]
+    DeepbindUnsupported,
+    #[error("{path:?} was built for {found}, but this process is {expected}")]
+    ArchMismatch {
+        path: PathBuf,
+        expected: String,
+        found: String,
+    },
+    #[error("{path:?} depends upon libraries that could not be found: {}", .names.join(", "))]
+    MissingDependencies {
+        path: PathBuf,
+        names: Vec<String>,
+    },
+    #[error("{path:?} references symbols that could not be resolved: {}", .symbols.join(", "))]
+    UnresolvedSymbols {
+        path: PathBuf,
+        symbols: Vec<String>,
+    },
+    #[error("{path:?} does not declare its ABI version via a `HOTLIB_ABI_VERSION` static")]
+    AbiVersionMissing {
+        path: PathBuf,
+    },
+    #[error("{path:?} declares ABI version {found}, but only versions {}..={} are accepted", .accepted.start(), .accepted.end())]
+    AbiVersionMismatch {
+        path: PathBuf,
+        found: u32,
+        accepted: std::ops::RangeInclusive<u32>,
+    },
+    #[error("{path:?} does not export symbols required by the table: {}", .names.join(", "))]
+    MissingExports {
+        path: PathBuf,
+        names: Vec<String>,
+    },
+    #[error("{path:?} exports symbols whose signatures differ from the table: {}", .names.join(", "))]
+    ExportSignatureMismatch {
+        path: PathBuf,
+        names: Vec<String>,
+    },
+}
+
+/// Errors that might occur while resolving a
+/// `HotSymbol`.
+#[derive(Debug, Error)]
+pub enum SymbolError {
+    #[error("symbol `{name}` is missing from generation {generation} of the library: {err}")]
+    Missing {
+        name: String,
+        generation: u64,
+        err: libloading::Error,
+    },
+    #[error("symbol `{name}` disappeared in generation {generation} of the library (last resolved from generation {last_generation}): {err}")]
+    Disappeared {
+        name: String,
+        generation: u64,
+        last_generation: u64,
+        err: libloading::Error,
+    },
+}
+
+/// Errors that might occur while retrieving a
+/// symbol via `TempLibrary::get_checked`.
+#[derive(Debug, Error)]
+pub enum SignatureError {
+    #[error("symbol `{name}` is missing from the library: {err}")]
+    Missing {
+        name: String,
+        err: libloading::Error,
+    },
+    #[error("symbol `{name}` has no signature hash to check against, it may not have been exported via `#[export]`: {err}")]
+    Unchecked {
+        name: String,
+        err: libloading::Error,
+    },
+    #[error("symbol `{name}` does not have the expected signature `{expected}`")]
+    Mismatch {
+        name: String,
+        expected: String,
+    },
+}
+
+/// Errors that might occur within the deprecated
+/// `TempLibrary::new`.
+#[deprecated(note = "use `Loader` and `LoadError` instead")]
+#[derive(Debug)]
+pub enum CreateTempLibraryError {
+    CouldNotLoadDirectlyFromDylib {
+        path:  PathBuf,
+        error: LoadError
+    },
+    CannotGetMetadata {
+        path:  PathBuf,
+    },
+    CannotGetFileCreationTime {
+        path:     PathBuf,
+        metadata: std::fs::Metadata,
+    },
+    LoadError {
+        error: LoadError,
+    }
 }
 
 impl ExitStatusUnsuccessfulError {
@@ -374,8 +428,8 @@ pub fn watch(path: &Path) -> Result<Watch, WatchError> {
         let targets = pkg.get("targets")?.as_array()?;
         let target = targets.iter().find_map(|target| {
             let kind = target.get("kind")?.as_array()?;
-            if kind.iter().find(|k| k.as_str() == Some("dylib")).is_some() {
-                return Some(target);
+            if kind.iter().any(|k| k.as_str() == Some("dylib")) {
+                Some(target)
             } else {
                 None
             }
@@ -395,56 +449,76 @@ pub fn watch(path: &Path) -> Result<Watch, WatchError> {
         .parent()
         .expect("src root has no parent directory");
 
-    // Begin watching the src path.
-    let (tx, event_rx) = crossbeam_channel::unbounded();
-
-    let sender = ChannelSender(tx);
-
-    let mut watcher = notify::recommended_watcher(sender)?;
-    watcher.watch(src_dir_path, notify::RecursiveMode::Recursive)?;
-
-    // Collect the paths.
-    let manifest_path = path.to_path_buf();
-    let src_path = src_dir_path.to_path_buf();
+    // Begin watching the src path, taking an
+    // initial snapshot of the source contents so
+    // that we can detect real changes.
+    let events = Events::dir(src_dir_path)?;
 
     // Collect the package info.
+    let manifest_path = path.to_path_buf();
+    let builder = CargoBuilder::new(manifest_path.clone(), lib_name, target_dir_path);
     let package_info = PackageInfo {
-        manifest_path,
-        src_path,
-        lib_name,
-        target_dir_path,
+        manifest_path: Some(manifest_path),
+        src_path:      src_dir_path.to_path_buf(),
+        builder:       Box::new(builder),
     };
 
     Ok(Watch {
-        package_info,
-        _watcher: watcher,
-        event_rx,
+        package_info: Arc::new(package_info),
+        events,
+        staging: Staging::default(),
+        abi_versions: None,
     })
 }
 
-//------------------------[these are for `recommended_watcher`]
-type ChannelMessage   = Result<notify::Event, notify::Error>;
-type ChannelSendError =  crossbeam_channel::SendError<ChannelMessage>;
-
-struct ChannelSender(crossbeam_channel::Sender<ChannelMessage>);
-
-impl ChannelSender {
-    pub fn send(&mut self, msg: ChannelMessage) -> Result<(), ChannelSendError> {
-        self.0.send(msg)
-    }
-}
-
-impl EventHandler for ChannelSender {
-    fn handle_event(&mut self, event: Result<notify::Event, notify::Error>) {
-        let _ = self.send(event);
-    }
+/// Watch the library whose source code lives in
+/// the given directory, building it with the
+/// given `Builder`.
+///
+/// This allows for hot-loading libraries that are
+/// not built via `cargo build`, e.g. C or C++
+/// libraries built with `make` via a
+/// `CommandBuilder`.
+///
+/// The directory is watched recursively in the
+/// same manner as `watch`.
+pub fn watch_with_builder<B>(src_path: &Path, builder: B) -> Result<Watch, WatchError>
+where
+    B: 'static + Builder,
+{
+    let events = Events::dir(src_path)?;
+    let package_info = PackageInfo {
+        manifest_path: None,
+        src_path:      src_path.to_path_buf(),
+        builder:       Box::new(builder),
+    };
+    Ok(Watch {
+        package_info: Arc::new(package_info),
+        events,
+        staging: Staging::default(),
+        abi_versions: None,
+    })
 }
 
 impl Watch {
 
     /// The path to the package's `Cargo.toml`.
+    ///
+    /// # Panics
+    ///
+    /// Panics for watches created via
+    /// `watch_with_builder`, which have no manifest.
+    /// See `try_manifest_path`.
     pub fn manifest_path(&self) -> &Path {
-        &self.package_info.manifest_path
+        self.try_manifest_path()
+            .expect("`manifest_path` called on a watch created via `watch_with_builder`")
+    }
+
+    /// The path to the package's `Cargo.toml`, or
+    /// `None` for watches created via
+    /// `watch_with_builder`.
+    pub fn try_manifest_path(&self) -> Option<&Path> {
+        self.package_info.manifest_path.as_deref()
     }
 
     /// The path to the source directory being
@@ -453,30 +527,92 @@ impl Watch {
         &self.package_info.src_path
     }
 
+    /// Specify how the libraries built by this
+    /// watch's packages are staged before they are
+    /// loaded.
+    ///
+    /// By default this is `Staging::Copy`.
+    pub fn staging(mut self, staging: Staging) -> Self {
+        self.staging = staging;
+        self
+    }
+
+    /// Specify the ABI versions that the libraries
+    /// built by this watch's packages may declare.
+    ///
+    /// See `Loader::abi_versions`.
+    pub fn abi_versions(mut self, versions: RangeInclusive<u32>) -> Self {
+        self.abi_versions = Some(versions);
+        self
+    }
+
     /// Wait for the library to be re-built after
     /// some change.
+    ///
+    /// Events that leave the contents of every
+    /// watched file unchanged (e.g. an editor
+    /// re-writing identical contents or a
+    /// checkout touching mtimes) are ignored.
     pub fn next(&self) -> Result<Package, NextError> {
-        loop {
-            let event = match self.event_rx.recv() {
-                Err(_) => return Err(NextError::ChannelClosed),
-                Ok(event) => event,
-            };
-
-            if check_raw_event(event?)? {
-                return Ok(self.package());
-            }
-        }
+        self.events.next()?;
+        Ok(self.package())
     }
 
-    /// The same as `next`, but returns early if
-    /// there are no pending events.
+    /// The same as `next`, but returns immediately
+    /// rather than waiting for a change.
+    ///
+    /// Events that have not yet settled are checked
+    /// by a later call, and the readiness handle is
+    /// signalled once they are due.
     pub fn try_next(&self) -> Result<Option<Package>, NextError> {
-        for event in self.event_rx.try_iter() {
-            if check_raw_event(event?)? {
-                return Ok(Some(self.package()));
-            }
+        Ok(self.events.try_next()?.then(|| self.package()))
+    }
+
+    /// The same as `next`, but returns `None` if
+    /// no change occurs within the given duration.
+    pub fn next_timeout(&self, timeout: Duration) -> Result<Option<Package>, NextError> {
+        match Instant::now().checked_add(timeout) {
+            Some(deadline) => self.next_deadline(deadline),
+            None => self.next().map(Some),
         }
-        Ok(None)
+    }
+
+    /// The same as `next`, but returns `None` if
+    /// no change occurs before the given deadline.
+    pub fn next_deadline(&self, deadline: Instant) -> Result<Option<Package>, NextError> {
+        Ok(self.events.next_deadline(deadline)?.then(|| self.package()))
+    }
+
+    /// The channel on which raw file system events
+    /// are received.
+    ///
+    /// This allows for waiting on the `Watch`
+    /// alongside other channels via
+    /// `crossbeam_channel::select!`. Events
+    /// received from this channel should be passed
+    /// to `handle_event` to determine whether or
+    /// not they trigger a rebuild.
+    ///
+    /// The channel also receives events of kind
+    /// `EventKind::Other` once changes that had not
+    /// yet settled are due to be checked, which
+    /// must also be passed to `handle_event`.
+    pub fn receiver(&self) -> &crossbeam_channel::Receiver<WatchEvent> {
+        self.events.receiver()
+    }
+
+    /// Check an event received via `receiver`,
+    /// returning the package if the event should
+    /// trigger a rebuild.
+    pub fn handle_event(&self, event: WatchEvent) -> Result<Option<Package>, NextError> {
+        Ok(self.events.handle_event(event)?.then(|| self.package()))
+    }
+
+    /// Cause the next call to `next` or `try_next`
+    /// to yield the package immediately, whether or
+    /// not the contents of any file have changed.
+    pub fn force_rebuild(&self) {
+        self.events.force();
     }
 
     /// Manually retrieve the library's package
@@ -486,16 +622,54 @@ impl Watch {
     /// This is useful for triggering an initial
     /// build during model initialisation.
     pub fn package(&self) -> Package {
-        let info = &self.package_info;
-        Package { info }
+        let info = self.package_info.clone();
+        let staging = self.staging;
+        let abi_versions = self.abi_versions.clone();
+        Package { info, staging, abi_versions }
     }
 }
 
-impl<'a> Package<'a> {
+/// A readiness handle for event loop integration.
+///
+/// The file descriptor becomes readable whenever
+/// an event that may trigger a rebuild is queued
+/// (or `force_rebuild` is called), and is reset
+/// by `next`, `try_next`, `next_timeout`,
+/// `next_deadline` and `handle_event` once no
+/// more events remain queued. This allows for
+/// registering the `Watch` with epoll, mio or
+/// calloop and calling `try_next` upon wake-up.
+///
+/// Note that a wake-up does not guarantee that
+/// `try_next` will yield a package, as the event
+/// may leave the contents of all files unchanged
+/// or may not yet have settled.
+#[cfg(target_os = "linux")]
+impl std::os::unix::io::AsRawFd for Watch {
+    fn as_raw_fd(&self) -> std::os::unix::io::RawFd {
+        self.events.readiness().fd()
+    }
+}
+
+impl Package {
 
     /// The path to the package's `Cargo.toml`.
+    ///
+    /// # Panics
+    ///
+    /// Panics for packages of watches created via
+    /// `watch_with_builder`, which have no manifest.
+    /// See `try_manifest_path`.
     pub fn manifest_path(&self) -> &Path {
-        &self.info.manifest_path
+        self.try_manifest_path()
+            .expect("`manifest_path` called on a package watched via `watch_with_builder`")
+    }
+
+    /// The path to the package's `Cargo.toml`, or
+    /// `None` for packages of watches created via
+    /// `watch_with_builder`.
+    pub fn try_manifest_path(&self) -> Option<&Path> {
+        self.info.manifest_path.as_deref()
     }
 
     /// The path to the source directory being watched.
@@ -505,22 +679,65 @@ impl<'a> Package<'a> {
 
     /// Builds the package's dynamic library target.
     pub fn build(&self) -> Result<Build, BuildError> {
-        let PackageInfo {
-            ref manifest_path,
-            ref lib_name,
-            ref target_dir_path,
-            ..
-        } = self.info;
-
-        // Tell cargo to compile the package.
-        let manifest_path_str = format!("{}", manifest_path.display());
-        let output = std::process::Command::new("cargo")
-            .arg("build")
-            .arg("--manifest-path")
-            .arg(&manifest_path_str)
-            .arg("--lib")
-            .arg("--release")
-            .output()?;
+        let build = self.info.builder.build()?;
+        Ok(self.configure(build))
+    }
+
+    /// The `Builder` used to build the package.
+    pub fn builder(&self) -> &dyn Builder {
+        &*self.info.builder
+    }
+
+    // Apply the `Watch`'s loading configuration to
+    // a build of this package.
+    pub(crate) fn configure(&self, mut build: Build) -> Build {
+        build.abi_versions = self.abi_versions.clone();
+        build.staging(self.staging)
+    }
+}
+
+impl std::fmt::Debug for PackageInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("PackageInfo")
+            .field("manifest_path", &self.manifest_path)
+            .field("src_path", &self.src_path)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Build {
+
+    /// A successful build of the dynamic library at
+    /// the given path, completed now.
+    ///
+    /// Useful for implementing custom `Builder`s
+    /// that do not run a process.
+    pub fn new(dylib_path: impl Into<PathBuf>) -> Self {
+        let output = std::process::Output {
+            status: Default::default(),
+            stdout: vec![],
+            stderr: vec![],
+        };
+        Build {
+            dylib_path:   dylib_path.into(),
+            timestamp:    SystemTime::now(),
+            output,
+            staging:      Staging::default(),
+            options:      LoadOptions::default(),
+            abi_versions: None,
+        }
+    }
+
+    /// Produce the `Build` from the output of the
+    /// process that built the dynamic library at
+    /// the given path.
+    ///
+    /// Returns an error if the output indicates
+    /// that the process failed.
+    pub fn from_output(
+        dylib_path: impl Into<PathBuf>,
+        output: std::process::Output,
+    ) -> Result<Self, BuildError> {
 
         // Check the exit status.
         if let Some(err) = ExitStatusUnsuccessfulError::from_output(&output) {
@@ -531,17 +748,47 @@ impl<'a> Package<'a> {
         let timestamp = SystemTime::now();
 
         Ok(Build {
+            dylib_path: dylib_path.into(),
             timestamp,
             output,
-            lib_name:        lib_name.to_string(),
-            target_dir_path: target_dir_path.to_path_buf(),
+            staging: Staging::default(),
+            options: LoadOptions::default(),
+            abi_versions: None,
         })
     }
-}
 
-impl Build {
+    /// Specify how the library is staged before it
+    /// is loaded via `load`.
+    ///
+    /// By default this is `Staging::Copy`, or the
+    /// `Watch`'s staging for builds produced by its
+    /// packages.
+    pub fn staging(mut self, staging: Staging) -> Self {
+        self.staging = staging;
+        self
+    }
 
-    /// The output of the cargo process.
+    /// Specify how the staged library is loaded via
+    /// `load`.
+    pub fn load_options(mut self, options: LoadOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Specify the ABI versions that the library may
+    /// declare when loaded via `load`.
+    ///
+    /// By default the version is not checked, or
+    /// the `Watch`'s accepted versions are used for
+    /// builds produced by its packages. See
+    /// `Loader::abi_versions`.
+    pub fn abi_versions(mut self, versions: RangeInclusive<u32>) -> Self {
+        self.abi_versions = Some(versions);
+        self
+    }
+
+    /// The output of the build process (e.g.
+    /// cargo).
     pub fn cargo_output(&self) -> &std::process::Output {
         &self.output
     }
@@ -553,19 +800,7 @@ impl Build {
 
     /// The path to the generated dylib target.
     pub fn dylib_path(&self) -> PathBuf {
-        let file_stem = self.file_stem();
-        self.target_dir_path
-            .join("release")
-            .join(file_stem)
-            .with_extension(dylib_ext())
-    }
-
-    /// The path to the temporary dynamic library
-    /// clone that will be created upon `load`.
-    pub fn tmp_dylib_path(&self) -> PathBuf {
-        tmp_dir()
-            .join(self.tmp_file_stem())
-            .with_extension(dylib_ext())
+        self.dylib_path.clone()
     }
 
     /// Copy the library to the platform's
@@ -575,48 +810,18 @@ impl Build {
     /// Note that the copied dynamic library will
     /// be removed on `Drop`.
     pub fn load(&self) -> Result<TempLibrary, LoadError> {
+        self.loader().load()
+    }
 
-        let dylib_path = self.dylib_path();
-        let tmp_path   = self.tmp_dylib_path();
-        let tmp_dir    = tmp_path.parent().expect("temp dylib path has no parent");
-
-        // If the library already exists, load it.
-        loop {
-
-            if tmp_path.exists() {
-
-                // This is some voodoo to enable
-                // reloading of dylib on mac os
-                if cfg!(target_os = "macos") {
-                    std::process::Command::new("install_name_tool")
-                        .current_dir(tmp_dir)
-                        .arg("-id")
-                        .arg("''")
-                        .arg(
-                            tmp_path
-                                .file_name()
-                                .expect("temp dylib path has no file name"),
-                        )
-                        .output()
-                        .expect("ls command failed to start");
-                }
-
-                let lib = libloading::Library::new(&tmp_path)
-                    .map(Some)
-                    .map_err(|err| LoadError::Library { err })?;
-                let path = tmp_path;
-                let build_timestamp = self.timestamp;
-                let tmp = TempLibrary {
-                    build_timestamp,
-                    path,
-                    lib,
-                };
-                return Ok(tmp);
-            }
-
-            // Copy the dylib to the tmp location.
-            std::fs::create_dir_all(tmp_dir).map_err(|err| LoadError::Io { err })?;
-            std::fs::copy(&dylib_path, &tmp_path).map_err(|err| LoadError::Io { err })?;
+    /// A `Loader` for the generated dylib target.
+    pub fn loader(&self) -> Loader {
+        let loader = Loader::new(self.dylib_path())
+            .build_timestamp(self.timestamp)
+            .staging(self.staging)
+            .options(self.options);
+        match self.abi_versions {
+            Some(ref versions) => loader.abi_versions(versions.clone()),
+            None => loader,
         }
     }
 
@@ -630,38 +835,28 @@ impl Build {
         let dylib_path = self.dylib_path();
         libloading::Library::new(dylib_path)
     }
+}
 
-    // The file stem of the built dynamic library.
-    fn file_stem(&self) -> String {
-
-        // TODO: On windows, the generated lib
-        // does not contain the "lib" prefix.
-        //
-        // A proper solution would likely involve
-        // retrieving the file stem from cargo
-        // itself.
-        #[cfg(target_os = "windows")]
-        {
-            format!("{}", self.lib_name)
-        }
+impl TempLibrary {
 
-        #[cfg(not(target_os = "windows"))]
-        {
-            format!("lib{}", self.lib_name)
+    /// Copy the library at the given path to the
+    /// temporary directory and load it from there.
+    ///
+    /// The library name is no longer used, as
+    /// copies are named after their contents.
+    #[deprecated(note = "use `Loader::new(dylib_path).load()` instead")]
+    #[allow(deprecated, clippy::ptr_arg, clippy::result_large_err)]
+    pub fn new(dylib_path: &PathBuf, lib_name: &str) -> Result<Self, CreateTempLibraryError> {
+        let _ = lib_name;
+        if dylib_path.metadata().is_err() {
+            let path = dylib_path.clone();
+            return Err(CreateTempLibraryError::CannotGetMetadata { path });
         }
+        Loader::new(dylib_path)
+            .load()
+            .map_err(|error| CreateTempLibraryError::LoadError { error })
     }
 
-    // Produce the file stem for the temporary
-    // dynamic library clone that will be created
-    // upon `load`.
-    fn tmp_file_stem(&self) -> String {
-        let timestamp_slug = slugify(format!("{}", humantime::format_rfc3339(self.timestamp)));
-        format!("{}-{}", self.file_stem(), timestamp_slug)
-    }
-}
-
-impl TempLibrary {
-
     /// The inner `libloading::Library`.
     ///
     /// This may also be accessed via the `Deref`
@@ -680,9 +875,131 @@ impl TempLibrary {
 
     /// The path at which the loaded temporary
     /// library is located.
+    ///
+    /// For libraries staged via `Staging::Memfd`,
+    /// this is the name of the in-memory file as it
+    /// appears in `/proc/self/maps`.
     pub fn path(&self) -> &Path {
         &self.path
     }
+
+    /// The strategy via which the library was
+    /// staged.
+    ///
+    /// This is never `Staging::Auto`, but rather
+    /// the strategy that `Auto` settled on.
+    pub fn staging(&self) -> Staging {
+        self.staging
+    }
+
+    /// The time taken to stage the library, not
+    /// including the time taken to load it.
+    pub fn staging_duration(&self) -> Duration {
+        self.staging_duration
+    }
+
+    /// The options with which the library was
+    /// loaded.
+    pub fn options(&self) -> LoadOptions {
+        self.options
+    }
+
+    /// The dependencies that were staged and loaded
+    /// alongside the library.
+    ///
+    /// See `Loader::stage_dependencies`.
+    pub fn dependencies(&self) -> &[TempLibrary] {
+        &self.dependencies
+    }
+
+    /// Resolve every symbol of the given table from
+    /// the library in one call.
+    ///
+    /// Fails with `LoadError::MissingExports` naming
+    /// every missing symbol if any could not be
+    /// found, or otherwise with
+    /// `LoadError::ExportSignatureMismatch` naming
+    /// every symbol whose exported signature hash
+    /// differs from its type within the table.
+    ///
+    /// # Safety
+    ///
+    /// The table's function pointers are not tied to
+    /// the lifetime of the library. The caller must
+    /// not call them once the library is dropped, for
+    /// example by keeping the table alongside it as
+    /// `RealtimeLibrary` does.
+    ///
+    /// Symbols are only type-checked if the library
+    /// exports their signature hashes, as with
+    /// `#[hotlib_plugin::export]`. Otherwise the caller
+    /// must ensure each symbol matches its type within
+    /// the table.
+    pub unsafe fn table<T: SymbolTable>(&self) -> Result<T, LoadError> {
+        T::load(self).map_err(|err| {
+            let path = self.path().to_path_buf();
+            match err.missing.is_empty() {
+                false => LoadError::MissingExports { path, names: err.missing },
+                true => LoadError::ExportSignatureMismatch { path, names: err.mismatched },
+            }
+        })
+    }
+
+    /// Call the library's `hotlib_on_load` hook if it
+    /// was deferred via `Loader::defer_on_load` and
+    /// has not been called yet.
+    ///
+    /// This does nothing if the library's hooks are
+    /// disabled or the hook was already called.
+    pub fn on_load(&self) {
+        if let Some(ref hooks) = self.hooks {
+            hooks.load(self.lib());
+        }
+    }
+
+    /// Unload the library and remove its temporary
+    /// copy, verifying that the library is no longer
+    /// mapped into the process.
+    ///
+    /// This is the same as dropping the
+    /// `TempLibrary`, where a library that remains
+    /// mapped is logged and counted by
+    /// `leaked_generations`. The library's
+    /// `hotlib_on_unload` hook is called first, and
+    /// staged dependencies are unloaded afterwards.
+    pub fn unload(mut self) -> UnloadOutcome {
+        self.close()
+    }
+
+    // Close the library, then remove the temporary
+    // copy once it has been checked for lingering
+    // mappings.
+    fn close(&mut self) -> UnloadOutcome {
+        let lib = match self.lib.take() {
+            None => return UnloadOutcome::Unloaded,
+            Some(lib) => lib,
+        };
+        tracing::info!("unloading {:?}", self);
+        if let Some(ref hooks) = self.hooks {
+            hooks.unload(&lib);
+        }
+        std::mem::drop(lib);
+        let outcome = unload::verify(&self.path, self.memfd.as_ref());
+        match self.memfd.take() {
+            // The dynamic linker identifies the library
+            // by its `/proc/self/fd` path, so the fd
+            // must remain reserved while the library
+            // remains mapped.
+            Some(memfd) if matches!(outcome, UnloadOutcome::StillMapped { .. }) => {
+                std::mem::forget(memfd);
+            }
+            Some(memfd) => std::mem::drop(memfd),
+            None => {
+                std::fs::remove_file(&self.path).ok();
+            }
+        }
+        outcome
+    }
 }
 
 impl std::ops::Deref for TempLibrary {
@@ -694,71 +1011,19 @@ impl std::ops::Deref for TempLibrary {
 
 impl Drop for TempLibrary {
     fn drop(&mut self) {
-        tracing::info!("dropping {:?}",self);
-        std::mem::drop(self.lib.take());
-        std::fs::remove_file(&self.path).ok();
+        self.close();
     }
 }
 
-// The temporary directory used by hotlib.
-fn tmp_dir() -> PathBuf {
-    std::env::temp_dir().join("hotlib")
-}
-
 // Whether or not the given event should trigger
 // a rebuild.
 fn _check_event(_event: notify::Event) -> bool {
     true
 }
 
-// Whether or not the given event should trigger
-// a rebuild.
-fn check_raw_event(event: notify::Event) -> Result<bool, NextError> {
-
-    use notify::event::*;
-
-    let kind = &event.kind;
-
-    let close_write = match event.kind {
-        EventKind::Access(AccessKind::Close(AccessMode::Write)) => true,
-        _ => false,
-    };
-
-    Ok(
-        kind.is_create() 
-        || kind.is_remove()
-        || kind.is_modify()
-        || close_write
-    )
-}
-
 // Get the dylib extension for this platform.
 //
 // TODO: This should be exposed from cargo.
 fn dylib_ext() -> &'static str {
-
-    #[cfg(target_os = "linux")]
-    {
-        return "so";
-    }
-
-    #[cfg(any(target_os = "macos", target_os = "ios"))]
-    {
-        return "dylib";
-    }
-
-    #[cfg(target_os = "windows")]
-    {
-        return "dll";
-    }
-
-    #[cfg(not(any(
-        target_os = "linux",
-        target_os = "macos",
-        target_os = "ios",
-        target_os = "windows"
-    )))]
-    {
-        panic!("unknown dynamic library for this platform")
-    }
+    std::env::consts::DLL_EXTENSION
 }
@@ -0,0 +1,816 @@
+//! Loading dynamic libraries via a uniquely named
+//! temporary copy.
+
+use crate::deps::Needed;
+use crate::events::{HashWriter, STABLE_INTERVAL};
+use crate::hooks::Hooks;
+use crate::object::Object;
+use crate::{hash_bytes, hash_file, hooks, services, tmp, validate, LoadError, SavedState, TempLibrary};
+use std::ffi::{OsStr, OsString};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{self, AtomicUsize};
+use std::time::{Instant, SystemTime};
+
+/// Loads a dynamic library by copying it to a
+/// uniquely named temporary file and loading the
+/// copy.
+///
+/// This is used by `Build::load`, but may also be
+/// used to hot-load libraries built by an external
+/// build system or shipped with the application.
+#[derive(Clone, Debug)]
+pub struct Loader {
+    dylib_path:      PathBuf,
+    build_timestamp: Option<SystemTime>,
+    tmp_dir:         Option<PathBuf>,
+    staging:         Staging,
+    options:         LoadOptions,
+    dependencies:    bool,
+    validate:        bool,
+    abi_versions:    Option<RangeInclusive<u32>>,
+    hooks:           bool,
+    defer_on_load:   bool,
+    state:           Option<SavedState>,
+}
+
+/// Options that determine how the staged library
+/// is loaded.
+///
+/// On unix these map to the flags passed to
+/// `dlopen`. By default, symbols are resolved
+/// lazily upon first use and are not made
+/// available to subsequently loaded libraries
+/// (i.e. `RTLD_LAZY | RTLD_LOCAL`). These options
+/// are ignored on other platforms.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct LoadOptions {
+    now:      bool,
+    global:   bool,
+    deepbind: bool,
+}
+
+/// How the library is staged before it is loaded.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Staging {
+    /// Try `Reflink`, then `Hardlink` once the
+    /// library is finalised, falling back to `Copy`.
+    ///
+    /// The library is considered finalised if it
+    /// has not changed since it was hashed and was
+    /// last modified at least 50ms before loading,
+    /// as with the stability check of single file
+    /// watches.
+    Auto,
+    /// Copy the library to a file within this
+    /// process' session directory.
+    #[default]
+    Copy,
+    /// Create a copy-on-write clone of the library
+    /// within this process' session directory via
+    /// the `FICLONE` ioctl.
+    ///
+    /// This is near instant, but is only supported
+    /// on Linux by filesystems such as btrfs and
+    /// xfs, and only when the session directory
+    /// lives on the same filesystem as the library.
+    Reflink,
+    /// Hard link the library into this process'
+    /// session directory.
+    ///
+    /// The link shares its contents with the
+    /// original, so this is only safe if the
+    /// original is replaced (e.g. via an atomic
+    /// rename, as cargo does) rather than rewritten
+    /// in place while the library is loaded.
+    /// Requires that the session directory lives on
+    /// the same filesystem as the library. Not
+    /// supported on macOS, where the staged library
+    /// is modified before loading.
+    Hardlink,
+    /// Copy the library into an anonymous
+    /// in-memory file via `memfd_create` and load
+    /// it via `/proc/self/fd`.
+    ///
+    /// This avoids touching the temporary directory
+    /// altogether. Only supported on Linux.
+    Memfd,
+}
+
+// Incremented for every temporary copy created by
+// this process so that no two copies share a path.
+static GENERATION: AtomicUsize = AtomicUsize::new(0);
+
+impl Loader {
+
+    /// Create a `Loader` for the dynamic library at
+    /// the given path.
+    pub fn new(dylib_path: impl Into<PathBuf>) -> Self {
+        Loader {
+            dylib_path:      dylib_path.into(),
+            build_timestamp: None,
+            tmp_dir:         None,
+            staging:         Staging::default(),
+            options:         LoadOptions::default(),
+            dependencies:    true,
+            validate:        true,
+            abi_versions:    None,
+            hooks:           true,
+            defer_on_load:   false,
+            state:           None,
+        }
+    }
+
+    /// Specify the moment at which the library was
+    /// built.
+    ///
+    /// By default this is the creation time of the
+    /// dylib file, falling back to its modification
+    /// time on filesystems that do not record
+    /// creation time.
+    pub fn build_timestamp(mut self, timestamp: SystemTime) -> Self {
+        self.build_timestamp = Some(timestamp);
+        self
+    }
+
+    /// Specify the directory in which the temporary
+    /// copy is created.
+    ///
+    /// By default this is `hotlib::tmp_dir()`.
+    pub fn tmp_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.tmp_dir = Some(dir.into());
+        self
+    }
+
+    /// Specify how the library is staged before it
+    /// is loaded.
+    ///
+    /// By default this is `Staging::Copy`.
+    pub fn staging(mut self, staging: Staging) -> Self {
+        self.staging = staging;
+        self
+    }
+
+    /// Specify how the staged library is loaded.
+    pub fn options(mut self, options: LoadOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Specify whether or not the shared libraries
+    /// that the library depends upon are staged and
+    /// loaded alongside it.
+    ///
+    /// This allows for loading libraries built with
+    /// `-C prefer-dynamic` or against other dylib
+    /// crates, whose `libstd-*.so` and sibling
+    /// `lib*.so` dependencies would otherwise only
+    /// be found relative to the original library or
+    /// via `LD_LIBRARY_PATH`. Dependencies are
+    /// searched for within the library's `$ORIGIN`
+    /// relative runpath, its directory, the `deps`
+    /// directory beside it and the Rust sysroot.
+    /// Dependencies that are already loaded or
+    /// cannot be found (e.g. system libraries) are
+    /// left to the dynamic linker.
+    ///
+    /// Note that the dynamic linker resolves a
+    /// dependency to any already loaded library
+    /// with a matching soname, so a rebuilt
+    /// dependency only takes effect once every
+    /// library using the previous copy has been
+    /// dropped.
+    ///
+    /// Enabled by default. Only supported on Linux.
+    pub fn stage_dependencies(mut self, stage: bool) -> Self {
+        self.dependencies = stage;
+        self
+    }
+
+    /// Specify whether or not the library is checked
+    /// before it is loaded.
+    ///
+    /// This reports libraries built for another
+    /// architecture, `DT_NEEDED` dependencies that
+    /// cannot be found and undefined symbols that
+    /// cannot be resolved as a `LoadError`, rather
+    /// than a terse `dlerror` string or, with lazy
+    /// binding, a crash upon first call.
+    ///
+    /// Enabled by default. Only supported on Linux.
+    pub fn validate(mut self, validate: bool) -> Self {
+        self.validate = validate;
+        self
+    }
+
+    /// Specify the ABI versions that the library
+    /// may declare.
+    ///
+    /// The library declares its version by
+    /// exporting a `HOTLIB_ABI_VERSION` static:
+    ///
+    /// ```
+    /// #[no_mangle]
+    /// pub static HOTLIB_ABI_VERSION: u32 = 3;
+    /// ```
+    ///
+    /// The version is read right after the library
+    /// is loaded, and libraries that do not declare
+    /// an accepted version are unloaded and
+    /// rejected with a `LoadError`. The version
+    /// should be bumped upon any incompatible
+    /// change to the types shared between the
+    /// library and the host.
+    ///
+    /// By default the version is not checked.
+    pub fn abi_versions(mut self, versions: RangeInclusive<u32>) -> Self {
+        self.abi_versions = Some(versions);
+        self
+    }
+
+    /// Specify whether or not the library's
+    /// `hotlib_init`, `hotlib_on_load` and
+    /// `hotlib_on_unload` hooks are called.
+    ///
+    /// See `HostServices` and `HookContext` for the
+    /// signature of each. Hooks are not called for
+    /// staged dependencies.
+    ///
+    /// Enabled by default.
+    pub fn hooks(mut self, hooks: bool) -> Self {
+        self.hooks = hooks;
+        self
+    }
+
+    /// Specify whether or not calling the library's
+    /// `hotlib_on_load` hook is deferred until
+    /// `TempLibrary::on_load` is called, e.g. once
+    /// the library has become the current
+    /// generation.
+    ///
+    /// If the hook is never called, the library's
+    /// `hotlib_on_unload` hook is not called either.
+    /// `HotLibrary::reload` and
+    /// `RealtimeLibrary::reload` defer the hook
+    /// until the new generation is installed.
+    ///
+    /// Disabled by default.
+    pub fn defer_on_load(mut self, defer: bool) -> Self {
+        self.defer_on_load = defer;
+        self
+    }
+
+    /// Specify state to restore within the library
+    /// via its `hotlib_load_state` function, e.g. as
+    /// saved by the previous generation.
+    ///
+    /// The state is restored before the library's
+    /// `hotlib_on_load` hook is called. See
+    /// `TempLibrary::save_state`.
+    pub fn state(mut self, state: SavedState) -> Self {
+        self.state = Some(state);
+        self
+    }
+
+    /// The path to the dynamic library that will be
+    /// loaded.
+    pub fn dylib_path(&self) -> &Path {
+        &self.dylib_path
+    }
+
+    /// Copy the library to the platform's
+    /// temporary directory (or an in-memory file,
+    /// depending on the `Staging`) and load it from
+    /// there.
+    ///
+    /// The copy is named after the hash of the
+    /// library's contents along with the process ID
+    /// and a generation number. Temporary files are
+    /// created exclusively within this process'
+    /// session directory, which is only accessible
+    /// to the current user. The copy is hashed
+    /// before loading (while copying, or re-read if
+    /// it was reflinked or hard linked) to check that
+    /// it matches the original library.
+    ///
+    /// Note that the copied dynamic library will be
+    /// removed on `Drop`.
+    pub fn load(&self) -> Result<TempLibrary, LoadError> {
+        let metadata = self.dylib_path.metadata()?;
+        let build_timestamp = self
+            .build_timestamp
+            .or_else(|| metadata.created().ok())
+            .or_else(|| metadata.modified().ok())
+            .unwrap_or_else(SystemTime::now);
+
+        // The library is only read into memory if it
+        // is to be inspected, in which case it is
+        // parsed once for all inspections.
+        let bytes = match self.validate || self.dependencies {
+            true => std::fs::read(&self.dylib_path)?,
+            false => vec![],
+        };
+        let hash = match bytes.is_empty() {
+            true => hash_file(&self.dylib_path)?,
+            false => hash_bytes(&bytes),
+        };
+        let object = Object::parse(&self.dylib_path, &bytes);
+
+        if self.validate {
+            validate::check_arch(&self.dylib_path, &object)?;
+        }
+        let dependencies = match self.dependencies {
+            true => self.load_dependencies(&object)?,
+            false => vec![],
+        };
+        if self.validate {
+            validate::check_linkage(&self.dylib_path, &object)?;
+        }
+        // Release the contents before staging.
+        drop(object);
+        drop(bytes);
+
+        let start = Instant::now();
+        let (staging, path, memfd) = match self.staging {
+            Staging::Memfd => {
+                let name = self.tmp_file_name(hash);
+                let (path, memfd) = stage_memfd(&self.dylib_path, &name, hash)?;
+                (Staging::Memfd, path, Some(memfd))
+            }
+            staging => {
+                let tmp_dir = self.tmp_dir.clone().unwrap_or_else(tmp::tmp_dir);
+                let dir = tmp::session_dir(&tmp_dir)?;
+                let (staging, path) = match staging {
+                    Staging::Auto => self.stage_auto(&dir, hash, &metadata)?,
+                    staging => (staging, self.stage_file(staging, &dir, hash)?),
+                };
+                (staging, path, None)
+            }
+        };
+        let staging_duration = start.elapsed();
+        tracing::debug!("staged {:?} via {:?} in {:?}", path, staging, staging_duration);
+
+        let lib = match memfd {
+            Some(ref memfd) => load_memfd(memfd, self.options)?,
+            None => match load_copy(&path, self.options) {
+                Ok(lib) => lib,
+                Err(err) => {
+                    std::fs::remove_file(&path).ok();
+                    return Err(err);
+                }
+            },
+        };
+
+        let mut lib = TempLibrary {
+            build_timestamp,
+            path,
+            staging,
+            staging_duration,
+            options: self.options,
+            hooks: None,
+            lib: Some(lib),
+            memfd,
+            dependencies,
+        };
+        self.check_abi_version(&lib)?;
+        if self.hooks {
+            services::init(lib.lib());
+        }
+        if let Some(ref state) = self.state {
+            tracing::debug!("restoring state version {} within {:?}", state.version(), lib.path());
+            lib.restore_state(state);
+        }
+        if self.hooks {
+            lib.hooks = Some(Hooks::new(hooks::next_context(&self.dylib_path)));
+            if !self.defer_on_load {
+                lib.on_load();
+            }
+        }
+        Ok(lib)
+    }
+
+    // Check the version declared by the loaded
+    // library against the accepted versions, if
+    // any.
+    fn check_abi_version(&self, lib: &TempLibrary) -> Result<(), LoadError> {
+        let accepted = match self.abi_versions {
+            None => return Ok(()),
+            Some(ref versions) => versions,
+        };
+        let found = unsafe {
+            match lib.get::<*const u32>(b"HOTLIB_ABI_VERSION\0") {
+                Ok(version) => **version,
+                Err(_) => {
+                    let path = self.dylib_path.clone();
+                    return Err(LoadError::AbiVersionMissing { path });
+                }
+            }
+        };
+        if !accepted.contains(&found) {
+            return Err(LoadError::AbiVersionMismatch {
+                path:     self.dylib_path.clone(),
+                found,
+                accepted: accepted.clone(),
+            });
+        }
+        Ok(())
+    }
+
+    // Stage and load the dependencies of the library
+    // that would not otherwise be found.
+    fn load_dependencies(&self, object: &Object) -> Result<Vec<TempLibrary>, LoadError> {
+        let needed = match Needed::read(&self.dylib_path, object) {
+            None => return Ok(vec![]),
+            Some(needed) => needed,
+        };
+        let mut dependencies = vec![];
+        for name in &needed.names {
+            if let Some(dylib_path) = needed.locate(name) {
+                tracing::debug!("staging dependency {:?} of {:?}", dylib_path, self.dylib_path);
+                // Dependencies neither declare an ABI
+                // version nor have their hooks called.
+                let loader = Loader {
+                    dylib_path,
+                    build_timestamp: None,
+                    abi_versions: None,
+                    hooks: false,
+                    state: None,
+                    ..self.clone()
+                };
+                dependencies.push(loader.load()?);
+            }
+        }
+        Ok(dependencies)
+    }
+
+    // Try each staging strategy in turn, returning
+    // the first that succeeds. The library is only
+    // hard linked if it is finalised, given its
+    // metadata from before it was hashed.
+    fn stage_auto(
+        &self,
+        dir: &Path,
+        hash: u64,
+        metadata: &std::fs::Metadata,
+    ) -> Result<(Staging, PathBuf), LoadError> {
+        let mut strategies = [Staging::Reflink, Staging::Hardlink, Staging::Copy].iter();
+        loop {
+            let staging = *strategies.next().expect("`Copy` is always attempted");
+            if staging == Staging::Hardlink && !self.is_finalised(metadata) {
+                tracing::debug!("not hard linking {:?} as it may still be written", self.dylib_path);
+                continue;
+            }
+            match self.stage_file(staging, dir, hash) {
+                Ok(path) => return Ok((staging, path)),
+                Err(err) if staging != Staging::Copy => {
+                    tracing::debug!("failed to stage {:?} via {:?}: {}", self.dylib_path, staging, err);
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    // Whether or not the library has not changed
+    // since the given metadata was read and was last
+    // modified long enough ago to be considered
+    // completely written.
+    fn is_finalised(&self, metadata: &std::fs::Metadata) -> bool {
+        let current = match self.dylib_path.metadata() {
+            Ok(current) => current,
+            Err(_) => return false,
+        };
+        let modified = match (metadata.modified(), current.modified()) {
+            (Ok(before), Ok(now)) if before == now => now,
+            _ => return false,
+        };
+        let age = SystemTime::now().duration_since(modified).unwrap_or_default();
+        current.len() == metadata.len() && age >= STABLE_INTERVAL
+    }
+
+    // Stage the library within the given directory
+    // via the given strategy, checking that the
+    // staged file matches the expected hash.
+    fn stage_file(&self, staging: Staging, dir: &Path, hash: u64) -> Result<PathBuf, LoadError> {
+        let (path, result) = match staging {
+            Staging::Copy => {
+                let (path, file) = self.create_tmp_file(dir, hash)?;
+                let result = copy_into(&self.dylib_path, &path, file, hash);
+                (path, result)
+            }
+            Staging::Reflink => {
+                if !cfg!(target_os = "linux") {
+                    return Err(LoadError::StagingUnsupported { staging });
+                }
+                let (path, file) = self.create_tmp_file(dir, hash)?;
+                let result = reflink_into(&self.dylib_path, &file);
+                (path, result)
+            }
+            Staging::Hardlink => {
+                if cfg!(target_os = "macos") {
+                    return Err(LoadError::StagingUnsupported { staging });
+                }
+                (self.hard_link_tmp_file(dir, hash)?, Ok(()))
+            }
+            Staging::Auto | Staging::Memfd => unreachable!("not a file staging strategy"),
+        };
+
+        // Check the staged file is the library we
+        // hashed, unless it was hashed while copying.
+        let result = result.and_then(|()| match staging == Staging::Copy || hash_file(&path)? == hash {
+            true => Ok(()),
+            false => Err(LoadError::HashMismatch { path: path.clone() }),
+        });
+        match result {
+            Ok(()) => Ok(path),
+            Err(err) => {
+                std::fs::remove_file(&path).ok();
+                Err(err)
+            }
+        }
+    }
+
+    // Hard link the library to a new path within
+    // the given directory.
+    fn hard_link_tmp_file(&self, dir: &Path, hash: u64) -> Result<PathBuf, LoadError> {
+        loop {
+            let path = dir.join(self.tmp_file_name(hash));
+            match std::fs::hard_link(&self.dylib_path, &path) {
+                Ok(()) => return Ok(path),
+                Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => continue,
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+
+    // Exclusively create a new temporary file for
+    // a copy of the library with the given hash.
+    fn create_tmp_file(&self, dir: &Path, hash: u64) -> Result<(PathBuf, std::fs::File), LoadError> {
+        loop {
+            let path = dir.join(self.tmp_file_name(hash));
+            let mut options = std::fs::OpenOptions::new();
+            options.write(true).create_new(true);
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::OpenOptionsExt;
+                options.mode(0o700);
+            }
+            match options.open(&path) {
+                Ok(file) => return Ok((path, file)),
+                // Left behind by a previous process with
+                // the same ID, so try the next generation.
+                Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => continue,
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+
+    // A unique file name for a copy of the library
+    // with the given hash.
+    fn tmp_file_name(&self, hash: u64) -> OsString {
+        let stem = self
+            .dylib_path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let generation = GENERATION.fetch_add(1, atomic::Ordering::SeqCst);
+        let mut name = OsString::from(format!(
+            "{}-{:016x}-{}-{}",
+            stem,
+            hash,
+            std::process::id(),
+            generation,
+        ));
+        if let Some(ext) = self.dylib_path.extension() {
+            name.push(".");
+            name.push(ext);
+        }
+        name
+    }
+}
+
+// Copy the library into the newly created file,
+// checking that the bytes copied match the
+// expected hash.
+fn copy_into(
+    dylib_path: &Path,
+    tmp_path: &Path,
+    file: std::fs::File,
+    hash: u64,
+) -> Result<(), LoadError> {
+
+    // Copy, hashing the bytes as they are written in
+    // case the library changed since it was hashed.
+    let mut src = std::io::BufReader::new(std::fs::File::open(dylib_path)?);
+    let mut dst = HashWriter::new(std::io::BufWriter::new(file));
+    std::io::copy(&mut src, &mut dst)?;
+    if dst.finish() != hash {
+        return Err(LoadError::HashMismatch { path: tmp_path.to_path_buf() });
+    }
+    let file = dst.into_inner().into_inner().map_err(|err| err.into_error())?;
+    file.sync_all()?;
+    Ok(())
+}
+
+// Clone the library's extents into the newly
+// created file.
+#[cfg(target_os = "linux")]
+fn reflink_into(dylib_path: &Path, file: &std::fs::File) -> Result<(), LoadError> {
+    use std::os::unix::io::AsRawFd;
+    let src = std::fs::File::open(dylib_path)?;
+    if unsafe { libc::ioctl(file.as_raw_fd(), libc::FICLONE, src.as_raw_fd()) } < 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn reflink_into(_dylib_path: &Path, _file: &std::fs::File) -> Result<(), LoadError> {
+    Err(LoadError::StagingUnsupported { staging: Staging::Reflink })
+}
+
+// Load the temporary copy of the library.
+fn load_copy(tmp_path: &Path, options: LoadOptions) -> Result<libloading::Library, LoadError> {
+
+    // This is some voodoo to enable
+    // reloading of dylib on mac os
+    if cfg!(target_os = "macos") {
+        let tmp_dir = tmp_path.parent().expect("temp dylib path has no parent");
+        let output = std::process::Command::new("install_name_tool")
+            .current_dir(tmp_dir)
+            .arg("-id")
+            .arg("''")
+            .arg(
+                tmp_path
+                    .file_name()
+                    .expect("temp dylib path has no file name"),
+            )
+            .output()?;
+        if !output.status.success() {
+            tracing::warn!(
+                "install_name_tool failed: {}",
+                String::from_utf8_lossy(&output.stderr),
+            );
+        }
+    }
+
+    open(tmp_path, options)
+}
+
+// Copy the library into a new sealed memfd.
+//
+// Returns the name of the memfd as it appears in
+// `/proc/self/maps` along with the memfd, which
+// must remain open while the library is loaded so
+// that no other library may be loaded via the same
+// `/proc/self/fd` path.
+#[cfg(target_os = "linux")]
+fn stage_memfd(
+    dylib_path: &Path,
+    name: &OsStr,
+    hash: u64,
+) -> Result<(PathBuf, std::fs::File), LoadError> {
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::io::FromRawFd;
+
+    let c_name = std::ffi::CString::new(name.as_bytes())
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidInput, err))?;
+    let flags = libc::MFD_CLOEXEC | libc::MFD_ALLOW_SEALING;
+    let fd = unsafe { libc::memfd_create(c_name.as_ptr(), flags) };
+    if fd < 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    let file = unsafe { std::fs::File::from_raw_fd(fd) };
+    let path = PathBuf::from(format!("/memfd:{}", name.to_string_lossy()));
+
+    // Copy, hashing the bytes as they are written in
+    // case the library changed since it was hashed.
+    let mut src = std::io::BufReader::new(std::fs::File::open(dylib_path)?);
+    let mut dst = HashWriter::new(&file);
+    std::io::copy(&mut src, &mut dst)?;
+    if dst.finish() != hash {
+        return Err(LoadError::HashMismatch { path });
+    }
+
+    // Seal the memfd so that its contents may no
+    // longer change.
+    let seals = libc::F_SEAL_SHRINK | libc::F_SEAL_GROW | libc::F_SEAL_WRITE | libc::F_SEAL_SEAL;
+    if unsafe { libc::fcntl(fd, libc::F_ADD_SEALS, seals) } < 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok((path, file))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn stage_memfd(
+    _dylib_path: &Path,
+    _name: &OsStr,
+    _hash: u64,
+) -> Result<(PathBuf, std::fs::File), LoadError> {
+    Err(LoadError::StagingUnsupported { staging: Staging::Memfd })
+}
+
+// Load the library from the given memfd via
+// `/proc/self/fd`.
+fn load_memfd(
+    memfd: &std::fs::File,
+    options: LoadOptions,
+) -> Result<libloading::Library, LoadError> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::io::AsRawFd;
+        let fd_path = format!("/proc/self/fd/{}", memfd.as_raw_fd());
+        open(Path::new(&fd_path), options)
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = (memfd, options);
+        Err(LoadError::StagingUnsupported { staging: Staging::Memfd })
+    }
+}
+
+// Load the library at the given path with the
+// given options.
+#[cfg(unix)]
+fn open(path: &Path, options: LoadOptions) -> Result<libloading::Library, LoadError> {
+    let lib = libloading::os::unix::Library::open(Some(path), options.flags()?)?;
+    Ok(lib.into())
+}
+
+#[cfg(not(unix))]
+fn open(path: &Path, _options: LoadOptions) -> Result<libloading::Library, LoadError> {
+    Ok(libloading::Library::new(path)?)
+}
+
+impl LoadOptions {
+
+    /// Resolve all undefined symbols when the
+    /// library is loaded (`RTLD_NOW`) rather than
+    /// upon first use, so that missing symbols are
+    /// reported as a `LoadError` rather than
+    /// aborting the process at the first call.
+    pub fn now(mut self, now: bool) -> Self {
+        self.now = now;
+        self
+    }
+
+    /// Make the library's symbols available to
+    /// subsequently loaded libraries
+    /// (`RTLD_GLOBAL`).
+    pub fn global(mut self, global: bool) -> Self {
+        self.global = global;
+        self
+    }
+
+    /// Prefer the library's own symbols over those
+    /// of the same name already loaded into the
+    /// process (`RTLD_DEEPBIND`).
+    ///
+    /// This is useful for libraries that bundle
+    /// their own copy of a C dependency. Only
+    /// supported on Linux with glibc.
+    pub fn deepbind(mut self, deepbind: bool) -> Self {
+        self.deepbind = deepbind;
+        self
+    }
+
+    /// Whether or not `RTLD_NOW` is used.
+    pub fn is_now(&self) -> bool {
+        self.now
+    }
+
+    /// Whether or not `RTLD_GLOBAL` is used.
+    pub fn is_global(&self) -> bool {
+        self.global
+    }
+
+    /// Whether or not `RTLD_DEEPBIND` is used.
+    pub fn is_deepbind(&self) -> bool {
+        self.deepbind
+    }
+
+    // The flags to pass to `dlopen`.
+    #[cfg(unix)]
+    fn flags(&self) -> Result<std::os::raw::c_int, LoadError> {
+        let mut flags = match self.now {
+            true => libc::RTLD_NOW,
+            false => libc::RTLD_LAZY,
+        };
+        flags |= match self.global {
+            true => libc::RTLD_GLOBAL,
+            false => libc::RTLD_LOCAL,
+        };
+        if self.deepbind {
+            #[cfg(all(target_os = "linux", target_env = "gnu"))]
+            {
+                flags |= libc::RTLD_DEEPBIND;
+            }
+
+            #[cfg(not(all(target_os = "linux", target_env = "gnu")))]
+            {
+                return Err(LoadError::DeepbindUnsupported);
+            }
+        }
+        Ok(flags)
+    }
+}
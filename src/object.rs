@@ -0,0 +1,50 @@
+//! A dynamic library read into memory once per
+//! load.
+//!
+//! Hashing the library, locating its dependencies
+//! and validating its linkage all inspect the same
+//! bytes, so the library is read and its ELF
+//! headers parsed once and shared between them.
+
+use std::path::Path;
+
+/// The parsed contents of a dynamic library.
+pub(crate) struct Object<'a> {
+    #[cfg(target_os = "linux")]
+    elf: Option<goblin::elf::Elf<'a>>,
+    #[cfg(not(target_os = "linux"))]
+    _bytes: std::marker::PhantomData<&'a [u8]>,
+}
+
+impl<'a> Object<'a> {
+
+    /// Parse the given contents of the library at the
+    /// given path.
+    ///
+    /// Contents that are not an ELF shared object
+    /// are logged and otherwise left to the dynamic
+    /// linker.
+    #[cfg(target_os = "linux")]
+    pub(crate) fn parse(dylib_path: &Path, bytes: &'a [u8]) -> Self {
+        let elf = match goblin::elf::Elf::parse(bytes) {
+            Ok(elf) => Some(elf),
+            Err(err) => {
+                tracing::warn!("failed to parse {:?} as ELF: {}", dylib_path, err);
+                None
+            }
+        };
+        Object { elf }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub(crate) fn parse(_dylib_path: &Path, _bytes: &'a [u8]) -> Self {
+        Object { _bytes: std::marker::PhantomData }
+    }
+
+    /// The library's ELF headers, or `None` if it is
+    /// not an ELF shared object.
+    #[cfg(target_os = "linux")]
+    pub(crate) fn elf(&self) -> Option<&goblin::elf::Elf<'a>> {
+        self.elf.as_ref()
+    }
+}
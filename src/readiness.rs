@@ -0,0 +1,109 @@
+//! A readiness handle that is signalled whenever
+//! an event is queued, allowing a `Watch` to be
+//! registered with an external event loop.
+
+/// Signalled by the notify event handler whenever
+/// a qualifying event is queued and cleared by the
+/// `Watch` before draining its event channel.
+///
+/// On Linux this is backed by an `eventfd`. With
+/// the `futures` feature enabled, signalling also
+/// wakes the task most recently registered via
+/// `register`.
+#[derive(Debug)]
+pub(crate) struct Readiness {
+    #[cfg(target_os = "linux")]
+    fd: std::os::unix::io::RawFd,
+    #[cfg(feature = "futures")]
+    waker: std::sync::Mutex<Option<std::task::Waker>>,
+}
+
+#[cfg(target_os = "linux")]
+impl Readiness {
+
+    pub(crate) fn new() -> std::io::Result<Self> {
+        let fd = unsafe { libc::eventfd(0, libc::EFD_CLOEXEC | libc::EFD_NONBLOCK) };
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(Readiness {
+            fd,
+            #[cfg(feature = "futures")]
+            waker: Default::default(),
+        })
+    }
+
+    /// Mark the handle as readable.
+    pub(crate) fn signal(&self) {
+        self.wake();
+        let one: u64 = 1;
+        let ptr = &one as *const u64 as *const libc::c_void;
+        unsafe {
+            libc::write(self.fd, ptr, std::mem::size_of::<u64>());
+        }
+    }
+
+    /// Reset the handle so that it is no longer
+    /// readable.
+    pub(crate) fn clear(&self) {
+        let mut count: u64 = 0;
+        let ptr = &mut count as *mut u64 as *mut libc::c_void;
+        unsafe {
+            libc::read(self.fd, ptr, std::mem::size_of::<u64>());
+        }
+    }
+
+    pub(crate) fn fd(&self) -> std::os::unix::io::RawFd {
+        self.fd
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for Readiness {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+impl Readiness {
+
+    pub(crate) fn new() -> std::io::Result<Self> {
+        Ok(Readiness {
+            #[cfg(feature = "futures")]
+            waker: Default::default(),
+        })
+    }
+
+    pub(crate) fn signal(&self) {
+        self.wake();
+    }
+
+    pub(crate) fn clear(&self) {}
+}
+
+impl Readiness {
+
+    /// Register the task to be woken upon the next
+    /// `signal`.
+    #[cfg(feature = "futures")]
+    pub(crate) fn register(&self, waker: &std::task::Waker) {
+        let mut slot = self.waker.lock().expect("readiness waker lock poisoned");
+        match *slot {
+            Some(ref w) if w.will_wake(waker) => (),
+            _ => *slot = Some(waker.clone()),
+        }
+    }
+
+    fn wake(&self) {
+        #[cfg(feature = "futures")]
+        {
+            let waker = self.waker.lock().expect("readiness waker lock poisoned").take();
+            if let Some(waker) = waker {
+                waker.wake();
+            }
+        }
+    }
+}
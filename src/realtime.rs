@@ -0,0 +1,370 @@
+//! Reloading libraries used by real-time threads
+//! (e.g. audio callbacks) that may neither block
+//! nor free memory.
+//!
+//! The current generation is published via an
+//! atomic pointer. Each reader announces the
+//! generation it is using via its own hazard
+//! pointer, and retired generations are only
+//! dropped by the non-real-time
+//! `RealtimeLibrary` once no reader announces them.
+
+use crate::{LoadError, Loader, TempLibrary};
+use std::ptr;
+use std::sync::atomic::{self, AtomicBool, AtomicPtr, AtomicU64};
+use std::sync::{Arc, Mutex};
+
+/// The non-real-time side of a library that is
+/// read from real-time threads.
+///
+/// Each generation pairs a `TempLibrary` with a
+/// table of values resolved from it (typically a
+/// struct of function pointers), which readers
+/// access via a single atomic load without
+/// blocking or allocating.
+///
+/// Retired generations are dropped by `swap` and
+/// `collect`, i.e. on the thread owning the
+/// `RealtimeLibrary`, and never on a reader's
+/// thread.
+pub struct RealtimeLibrary<T> {
+    shared: Arc<Shared<T>>,
+    next:   u64,
+}
+
+/// The real-time side of a `RealtimeLibrary`.
+///
+/// Created via `RealtimeLibrary::reader` on a
+/// non-real-time thread, then sent to the
+/// real-time thread. Reading never blocks,
+/// allocates or frees memory.
+///
+/// The reader should also be dropped on a
+/// non-real-time thread, as dropping the last
+/// handle to the library unloads it.
+pub struct RealtimeReader<T> {
+    shared: Arc<Shared<T>>,
+    hazard: Arc<Hazard<T>>,
+}
+
+struct Shared<T> {
+    current: AtomicPtr<Slot<T>>,
+    // Only accessed from non-real-time threads.
+    hazards: Mutex<Vec<Arc<Hazard<T>>>>,
+    retired: Mutex<Vec<*mut Slot<T>>>,
+    // The number of the current generation.
+    number:  AtomicU64,
+}
+
+// A single generation.
+struct Slot<T> {
+    number: u64,
+    // Declared before `lib` so that the table is
+    // dropped before the library is unloaded.
+    table:  T,
+    // Only `None` for generations created by tests
+    // of the reclamation scheme.
+    lib:    Option<TempLibrary>,
+}
+
+// The generation in use by a single reader.
+struct Hazard<T> {
+    slot:  AtomicPtr<Slot<T>>,
+    alive: AtomicBool,
+}
+
+// Slots are only dropped by non-real-time threads
+// once no reader is using them.
+unsafe impl<T: Send + Sync> Send for Shared<T> {}
+unsafe impl<T: Send + Sync> Sync for Shared<T> {}
+
+impl<T: Send + Sync> RealtimeLibrary<T> {
+
+    /// Create a library whose first generation is
+    /// the given library and table.
+    ///
+    /// The table should only contain values (e.g.
+    /// function pointers) that remain valid for as
+    /// long as the library is loaded.
+    pub fn new(lib: TempLibrary, table: T) -> Self {
+        Self::with_first(Some(lib), table)
+    }
+
+    // Create a library whose first generation is the
+    // given library and table.
+    fn with_first(lib: Option<TempLibrary>, table: T) -> Self {
+        let slot = Box::new(Slot { number: 0, table, lib });
+        let shared = Shared {
+            current: AtomicPtr::new(Box::into_raw(slot)),
+            hazards: Mutex::new(vec![]),
+            retired: Mutex::new(vec![]),
+            number:  AtomicU64::new(0),
+        };
+        RealtimeLibrary {
+            shared: Arc::new(shared),
+            next:   1,
+        }
+    }
+
+    /// Create a reader for use on a real-time
+    /// thread.
+    pub fn reader(&self) -> RealtimeReader<T> {
+        let hazard = Arc::new(Hazard {
+            slot:  AtomicPtr::new(ptr::null_mut()),
+            alive: AtomicBool::new(true),
+        });
+        self.shared
+            .hazards
+            .lock()
+            .expect("hazards lock poisoned")
+            .push(hazard.clone());
+        let shared = self.shared.clone();
+        RealtimeReader { shared, hazard }
+    }
+
+    /// Publish a new generation, returning its
+    /// number.
+    ///
+    /// The state of the current generation is first
+    /// transferred to the new library via
+    /// `TempLibrary::transfer_state`. Unless the
+    /// library was loaded via
+    /// `Loader::defer_on_load`, its `hotlib_on_load`
+    /// hook has already run before its state is
+    /// restored. Prefer `reload`, which restores the
+    /// state first.
+    ///
+    /// The previous generation is retired and
+    /// dropped by this or a later call to `swap` or
+    /// `collect` once no reader is using it.
+    pub fn swap(&mut self, lib: TempLibrary, table: T) -> u64 {
+        if let Some(current) = self.current_lib() {
+            current.transfer_state(&lib);
+        }
+        self.publish(Some(lib), table)
+    }
+
+    /// Load a new generation via the given `Loader`
+    /// and publish it along with the table produced
+    /// from it, returning its number.
+    ///
+    /// The state of the current generation is passed
+    /// to the new library via `Loader::state`, so
+    /// that it is restored before the library's
+    /// `hotlib_on_load` hook is called.
+    ///
+    /// The hook is deferred via
+    /// `Loader::defer_on_load` until the generation
+    /// is published, so a library whose table fails
+    /// to load has neither hook called. Readers may
+    /// call into the new generation before its hook
+    /// has returned, and the previous generation's
+    /// `hotlib_on_unload` hook is only called once it
+    /// is dropped, so the two may overlap.
+    pub fn reload<F>(&mut self, loader: &Loader, table: F) -> Result<u64, LoadError>
+    where
+        F: FnOnce(&TempLibrary) -> Result<T, LoadError>,
+    {
+        let loader = loader.clone().defer_on_load(true);
+        let lib = match self.current_lib().and_then(TempLibrary::save_state) {
+            Some(state) => loader.state(state).load()?,
+            None => loader.load()?,
+        };
+        let table = table(&lib)?;
+        Ok(self.publish(Some(lib), table))
+    }
+
+    // The library of the current generation.
+    fn current_lib(&self) -> Option<&TempLibrary> {
+        // Safe as the current slot is only retired
+        // by this `RealtimeLibrary`.
+        let current = unsafe { &*self.shared.current.load(atomic::Ordering::SeqCst) };
+        current.lib.as_ref()
+    }
+
+    // Publish a new generation, returning its
+    // number, then call its library's deferred
+    // `hotlib_on_load` hook, if any.
+    fn publish(&mut self, lib: Option<TempLibrary>, table: T) -> u64 {
+        let number = self.next;
+        self.next += 1;
+        let slot = Box::into_raw(Box::new(Slot { number, table, lib }));
+        let previous = self.shared.current.swap(slot, atomic::Ordering::SeqCst);
+        self.shared.number.store(number, atomic::Ordering::SeqCst);
+        if let Some(lib) = self.current_lib() {
+            lib.on_load();
+        }
+        self.shared
+            .retired
+            .lock()
+            .expect("retired lock poisoned")
+            .push(previous);
+        self.collect();
+        number
+    }
+
+    /// Drop every retired generation that is no
+    /// longer in use by a reader, returning the
+    /// number of retired generations that remain.
+    ///
+    /// A reader continues to use the generation it
+    /// last read until it reads again, so this
+    /// should be called periodically (e.g. from a
+    /// UI or file watching thread).
+    pub fn collect(&self) -> usize {
+        let in_use: Vec<*mut Slot<T>> = {
+            let mut hazards = self.shared.hazards.lock().expect("hazards lock poisoned");
+            hazards.retain(|hazard| hazard.alive.load(atomic::Ordering::SeqCst));
+            hazards
+                .iter()
+                .map(|hazard| hazard.slot.load(atomic::Ordering::SeqCst))
+                .collect()
+        };
+        let mut retired = self.shared.retired.lock().expect("retired lock poisoned");
+        retired.retain(|&slot| {
+            if in_use.contains(&slot) {
+                return true;
+            }
+            // Safe as the slot is no longer current and
+            // no reader has announced it.
+            unsafe { drop(Box::from_raw(slot)) };
+            false
+        });
+        retired.len()
+    }
+
+    /// The number of the current generation.
+    pub fn generation(&self) -> u64 {
+        self.shared.number.load(atomic::Ordering::SeqCst)
+    }
+}
+
+impl<T> RealtimeReader<T> {
+
+    /// The table of the current generation.
+    ///
+    /// This never blocks, allocates or frees
+    /// memory. The returned generation remains
+    /// loaded until the next call to `read` or
+    /// until the reader is dropped.
+    pub fn read(&mut self) -> &T {
+        &self.slot().table
+    }
+
+    /// The same as `read`, but also returns the
+    /// generation number.
+    pub fn read_generation(&mut self) -> (u64, &T) {
+        let slot = self.slot();
+        (slot.number, &slot.table)
+    }
+
+    // Announce and return the current slot.
+    fn slot(&mut self) -> &Slot<T> {
+        let shared = &self.shared;
+        let mut slot = shared.current.load(atomic::Ordering::SeqCst);
+        loop {
+            self.hazard.slot.store(slot, atomic::Ordering::SeqCst);
+
+            // If the slot is still current, it was
+            // announced before any retirement and so
+            // will not be dropped until the next read.
+            let current = shared.current.load(atomic::Ordering::SeqCst);
+            if current == slot {
+                break;
+            }
+            slot = current;
+        }
+        unsafe { &*slot }
+    }
+}
+
+impl<T> Drop for RealtimeReader<T> {
+    fn drop(&mut self) {
+        self.hazard.slot.store(ptr::null_mut(), atomic::Ordering::SeqCst);
+        self.hazard.alive.store(false, atomic::Ordering::SeqCst);
+    }
+}
+
+impl<T> Drop for Shared<T> {
+    fn drop(&mut self) {
+        let retired = self.retired.get_mut().expect("retired lock poisoned");
+        for slot in retired.drain(..) {
+            unsafe { drop(Box::from_raw(slot)) };
+        }
+        unsafe { drop(Box::from_raw(*self.current.get_mut())) };
+    }
+}
+
+impl<T> std::fmt::Debug for RealtimeLibrary<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("RealtimeLibrary")
+            .field("generation", &self.shared.number.load(atomic::Ordering::SeqCst))
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T> std::fmt::Debug for RealtimeReader<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("RealtimeReader").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    // A table recording the generation it belonged to
+    // once dropped.
+    struct Table {
+        number:  u64,
+        dropped: Arc<Mutex<HashSet<u64>>>,
+    }
+
+    impl Drop for Table {
+        fn drop(&mut self) {
+            self.dropped.lock().unwrap().insert(self.number);
+        }
+    }
+
+    #[test]
+    fn generations_are_only_dropped_once_unused() {
+        let dropped = Arc::new(Mutex::new(HashSet::new()));
+        let table = |number| Table { number, dropped: dropped.clone() };
+        let mut lib = RealtimeLibrary::with_first(None, table(0));
+        let stop = Arc::new(AtomicBool::new(false));
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let mut reader = lib.reader();
+                let (dropped, stop) = (dropped.clone(), stop.clone());
+                std::thread::spawn(move || {
+                    let mut reads = 0;
+                    while !stop.load(atomic::Ordering::SeqCst) {
+                        let (number, table) = reader.read_generation();
+                        assert_eq!(table.number, number);
+                        std::thread::yield_now();
+                        let in_use_dropped = dropped.lock().unwrap().contains(&number);
+                        assert!(!in_use_dropped, "generation {} dropped while in use", number);
+                        reads += 1;
+                    }
+                    reads
+                })
+            })
+            .collect();
+
+        for number in 1..=200 {
+            assert_eq!(lib.publish(None, table(number)), number);
+            std::thread::yield_now();
+        }
+        stop.store(true, atomic::Ordering::SeqCst);
+        for reader in readers {
+            assert!(reader.join().unwrap() > 0);
+        }
+
+        assert_eq!(lib.collect(), 0);
+        assert_eq!(lib.generation(), 200);
+        let dropped = dropped.lock().unwrap();
+        assert_eq!(dropped.len(), 200);
+        assert!(!dropped.contains(&200));
+    }
+}
@@ -0,0 +1,45 @@
+//! Passing host callbacks to loaded libraries, as
+//! described by `hotlib_plugin::HostServices`.
+
+use crate::HostServices;
+use std::sync::RwLock;
+
+// The type of the init function.
+type Init = extern "C" fn(&HostServices);
+
+// The services passed to each newly loaded
+// library, if any.
+static SERVICES: RwLock<Option<HostServices>> = RwLock::new(None);
+
+/// Register the table of host callbacks passed to
+/// each subsequently loaded library, along with its
+/// version.
+///
+/// Libraries receive the table via their
+/// `hotlib_init` function. See `HostServices`.
+///
+/// The version should be bumped upon any
+/// incompatible change to the table. Fields may be
+/// appended without bumping the version, as
+/// libraries built against a smaller table only
+/// access its prefix.
+pub fn set_host_services<T: Sync>(version: u32, table: &'static T) {
+    let services = HostServices::new(version, table);
+    *SERVICES.write().expect("host services lock poisoned") = Some(services);
+}
+
+/// Pass the registered host services to the
+/// library via its `hotlib_init` function, if both
+/// exist.
+pub(crate) fn init(lib: &libloading::Library) {
+    let services = match *SERVICES.read().expect("host services lock poisoned") {
+        None => return,
+        Some(services) => services,
+    };
+    let init = match unsafe { lib.get::<Init>(b"hotlib_init\0") } {
+        Ok(init) => init,
+        Err(_) => return,
+    };
+    tracing::debug!("calling hotlib_init with host services version {}", services.version());
+    init(&services);
+}
@@ -0,0 +1,40 @@
+//! Checking the type of a symbol against a hash of
+//! its signature exported alongside it by
+//! `#[hotlib_plugin::export]`.
+
+use crate::{signature_hash, SignatureError, TempLibrary};
+use hotlib_plugin::__private::SIGNATURE_PREFIX;
+
+impl TempLibrary {
+
+    /// Retrieve the symbol with the given name,
+    /// checking that its signature matches `T`.
+    ///
+    /// The library must export the symbol via
+    /// `#[export]`, which also exports a hash of its
+    /// signature. Returns an error if the symbol or
+    /// its signature hash is missing, or if the
+    /// signature differs from `T`, e.g. as the
+    /// function's signature changed in the latest
+    /// build.
+    pub fn get_checked<T: Copy>(&self, name: &str) -> Result<libloading::Symbol<'_, T>, SignatureError> {
+        let symbol_name = format!("{}\0", name);
+        let signature_name = format!("{}{}\0", SIGNATURE_PREFIX, name);
+        let symbol = unsafe { self.get::<T>(symbol_name.as_bytes()) }.map_err(|err| {
+            let name = name.to_string();
+            SignatureError::Missing { name, err }
+        })?;
+        let signature = unsafe { self.get::<extern "C" fn() -> u64>(signature_name.as_bytes()) }
+            .map_err(|err| {
+                let name = name.to_string();
+                SignatureError::Unchecked { name, err }
+            })?;
+        if signature() != signature_hash::<T>() {
+            return Err(SignatureError::Mismatch {
+                name:     name.to_string(),
+                expected: std::any::type_name::<T>().to_string(),
+            });
+        }
+        Ok(symbol)
+    }
+}
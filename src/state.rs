@@ -0,0 +1,103 @@
+//! Transferring library state between generations,
+//! as described by `hotlib_plugin::StateWriter`.
+
+use crate::{StateRef, StateWriter, TempLibrary};
+use std::os::raw::c_void;
+
+/// The state saved by a generation of a library,
+/// owned by the host.
+///
+/// Produced by `TempLibrary::save_state`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SavedState {
+    version: u32,
+    bytes:   Vec<u8>,
+}
+
+// The types of the state transfer functions.
+type Save = extern "C" fn(&mut StateWriter);
+type Load = extern "C" fn(&StateRef);
+
+impl SavedState {
+
+    /// Create a state with the given version and
+    /// bytes, e.g. as persisted by the host.
+    pub fn new(version: u32, bytes: Vec<u8>) -> Self {
+        SavedState { version, bytes }
+    }
+
+    /// The version with which the state was saved.
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// The saved bytes.
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Consume the state, producing the saved
+    /// bytes.
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+impl TempLibrary {
+
+    /// Ask the library to save its state via its
+    /// `hotlib_save_state` function.
+    ///
+    /// Returns `None` if the library does not export
+    /// the function or saved nothing.
+    pub fn save_state(&self) -> Option<SavedState> {
+        let save = unsafe { self.get::<Save>(b"hotlib_save_state\0") }.ok()?;
+        let mut state: Option<SavedState> = None;
+        let ptr = &mut state as *mut Option<SavedState> as *mut c_void;
+        let mut writer = unsafe { StateWriter::new(ptr, write_state) };
+        save(&mut writer);
+        state
+    }
+
+    /// Pass the given state to the library via its
+    /// `hotlib_load_state` function.
+    ///
+    /// Returns `false` if the library does not
+    /// export the function.
+    pub fn restore_state(&self, state: &SavedState) -> bool {
+        let load = match unsafe { self.get::<Load>(b"hotlib_load_state\0") } {
+            Ok(load) => load,
+            Err(_) => return false,
+        };
+        let state = unsafe { StateRef::new(state.version, &state.bytes) };
+        load(&state);
+        true
+    }
+
+    /// Save the state of this library and restore it
+    /// within the given library, e.g. the next
+    /// generation.
+    ///
+    /// This is called by `HotLibrary::swap` and
+    /// `RealtimeLibrary::swap`. Returns whether or
+    /// not any state was transferred.
+    pub fn transfer_state(&self, to: &TempLibrary) -> bool {
+        match self.save_state() {
+            Some(state) => {
+                tracing::debug!("transferring state version {} from {:?}", state.version, self.path());
+                to.restore_state(&state)
+            }
+            None => false,
+        }
+    }
+}
+
+// Copy the written bytes into the host's buffer.
+extern "C" fn write_state(state: *mut c_void, version: u32, data: *const u8, len: usize) {
+    let state = unsafe { &mut *(state as *mut Option<SavedState>) };
+    let bytes = match len {
+        0 => vec![],
+        len => unsafe { std::slice::from_raw_parts(data, len) }.to_vec(),
+    };
+    *state = Some(SavedState { version, bytes });
+}
@@ -0,0 +1,12 @@
+//! Resolving the symbols of a `SymbolTable` from a
+//! loaded library.
+
+use crate::{Symbols, TempLibrary};
+use std::os::raw::c_void;
+
+impl Symbols for TempLibrary {
+    fn symbol(&self, name: &str) -> Option<*const c_void> {
+        let symbol = unsafe { self.get::<*const c_void>(name.as_bytes()) }.ok()?;
+        Some(*symbol)
+    }
+}
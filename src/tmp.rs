@@ -0,0 +1,389 @@
+//! Management of the temporary directory in which
+//! library copies are created.
+//!
+//! Each process creates its copies within its own
+//! session directory, which it holds a lock on for
+//! its lifetime. The lock is released by the OS
+//! when the process exits or crashes, allowing
+//! stale session directories left behind by dead
+//! processes to be identified and removed.
+
+use crate::LoadError;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, RwLock};
+
+// The temporary directory configured via
+// `set_tmp_dir`.
+static TMP_DIR: RwLock<Option<PathBuf>> = RwLock::new(None);
+
+// The session directory of this process within
+// each temporary directory that has been used.
+static SESSIONS: Mutex<Option<HashMap<PathBuf, Session>>> = Mutex::new(None);
+
+// The name of the lock file within each session
+// directory.
+const LOCK_FILE_NAME: &str = ".lock";
+
+// A session directory owned by this process.
+struct Session {
+    dir:   PathBuf,
+    // Held open (and locked) for the lifetime of
+    // the process.
+    _lock: std::fs::File,
+}
+
+/// Set the directory in which temporary library
+/// copies are created by default.
+///
+/// By default this is a `hotlib` directory within
+/// the platform's temporary directory, specific to
+/// the current user on unix. The directory is
+/// created with permissions restricting it to the
+/// current user.
+pub fn set_tmp_dir(dir: impl Into<PathBuf>) {
+    *TMP_DIR.write().expect("tmp dir lock poisoned") = Some(dir.into());
+}
+
+/// The directory in which temporary library copies
+/// are created by default.
+pub fn tmp_dir() -> PathBuf {
+    if let Some(ref dir) = *TMP_DIR.read().expect("tmp dir lock poisoned") {
+        return dir.clone();
+    }
+
+    #[cfg(unix)]
+    {
+        let uid = unsafe { libc::getuid() };
+        std::env::temp_dir().join(format!("hotlib-{}", uid))
+    }
+
+    #[cfg(not(unix))]
+    {
+        std::env::temp_dir().join("hotlib")
+    }
+}
+
+/// Remove all temporary library copies left behind
+/// by processes that are no longer running from the
+/// default temporary directory.
+///
+/// This also happens automatically the first time
+/// a library is loaded within a temporary
+/// directory, but may be useful for cleaning up
+/// without loading a library.
+///
+/// Copies left behind by versions of hotlib prior
+/// to session directories (i.e. files named like
+/// `libfoo-2019-05-03t12-34-56-000000000z.so`
+/// directly within `$TMPDIR/hotlib`) are removed
+/// as well.
+///
+/// Returns the number of files removed.
+pub fn cleanup() -> std::io::Result<usize> {
+    let legacy = cleanup_legacy_dir(&std::env::temp_dir().join("hotlib"))?;
+    Ok(legacy + cleanup_dir(&tmp_dir())?)
+}
+
+/// The same as `cleanup`, but for the given
+/// temporary directory.
+///
+/// Only session directories (i.e. those named
+/// `{pid}-{n}` and holding a lock file) are
+/// considered, so that other data sharing the
+/// directory is left untouched.
+pub fn cleanup_dir(dir: &Path) -> std::io::Result<usize> {
+    let own = session_dirs();
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(err) => return Err(err),
+    };
+    let mut removed = 0;
+    for entry in entries {
+        let entry = entry?;
+        let path = entry.path();
+        if !is_session_name(&entry.file_name()) || !path.is_dir() || own.contains(&path) {
+            continue;
+        }
+
+        // The session is stale if its lock may be
+        // acquired, as the owning process has exited.
+        let lock = match std::fs::File::open(path.join(LOCK_FILE_NAME)) {
+            Ok(lock) => lock,
+            Err(_) => continue,
+        };
+        if lock.try_lock().is_err() {
+            continue;
+        }
+
+        removed += std::fs::read_dir(&path)?
+            .flatten()
+            .filter(|entry| entry.file_name() != LOCK_FILE_NAME)
+            .count();
+        std::fs::remove_dir_all(&path)?;
+        tracing::info!("removed stale hotlib session directory {:?}", path);
+    }
+    Ok(removed)
+}
+
+// Remove the copies left behind within the given
+// directory by versions of hotlib that named them
+// after the library's build timestamp, returning
+// the number of files removed.
+//
+// Copies that are still in use on platforms that
+// prevent their removal are left in place.
+fn cleanup_legacy_dir(dir: &Path) -> std::io::Result<usize> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(err) => return Err(err),
+    };
+    let mut removed = 0;
+    for entry in entries {
+        let entry = entry?;
+        let path = entry.path();
+        if !is_legacy_name(&entry.file_name()) || !path.is_file() {
+            continue;
+        }
+        match std::fs::remove_file(&path) {
+            Ok(()) => removed += 1,
+            Err(err) => tracing::debug!("failed to remove legacy hotlib copy {:?}: {}", path, err),
+        }
+    }
+    if removed > 0 {
+        tracing::info!("removed {} legacy hotlib copies from {:?}", removed, dir);
+    }
+    Ok(removed)
+}
+
+/// The session directory of this process within
+/// the given temporary directory, creating it (and
+/// sweeping stale sessions) if necessary.
+pub(crate) fn session_dir(tmp_dir: &Path) -> Result<PathBuf, LoadError> {
+    let dir = {
+        let mut sessions = SESSIONS.lock().expect("sessions lock poisoned");
+        let sessions = sessions.get_or_insert_with(HashMap::new);
+        if let Some(session) = sessions.get(tmp_dir) {
+            return Ok(session.dir.clone());
+        }
+        create_private_dir(tmp_dir)?;
+        let session = Session::create(tmp_dir)?;
+        let dir = session.dir.clone();
+        sessions.insert(tmp_dir.to_path_buf(), session);
+        dir
+    };
+
+    // Sweep sessions left behind by dead processes.
+    if let Err(err) = cleanup_dir(tmp_dir) {
+        tracing::warn!("failed to remove stale hotlib session directories: {}", err);
+    }
+    Ok(dir)
+}
+
+impl Session {
+
+    // Create and lock a new session directory for
+    // this process.
+    //
+    // The directory is named after the process ID,
+    // with a suffix in case a process with the same
+    // ID in another PID namespace shares the
+    // directory.
+    fn create(tmp_dir: &Path) -> Result<Self, LoadError> {
+        let pid = std::process::id();
+        for n in 0.. {
+            let dir = tmp_dir.join(format!("{}-{}", pid, n));
+            create_private_dir(&dir)?;
+            let lock = std::fs::OpenOptions::new()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .open(dir.join(LOCK_FILE_NAME))?;
+            if lock.try_lock().is_err() {
+                continue;
+            }
+
+            // Another process may have swept the
+            // directory between its creation and our
+            // acquiring the lock.
+            if !dir.join(LOCK_FILE_NAME).exists() {
+                continue;
+            }
+
+            // The directory may have been left behind
+            // by a dead process with the same ID.
+            for entry in std::fs::read_dir(&dir)?.flatten() {
+                if entry.file_name() != LOCK_FILE_NAME {
+                    std::fs::remove_file(entry.path()).ok();
+                }
+            }
+            return Ok(Session { dir, _lock: lock });
+        }
+        unreachable!()
+    }
+}
+
+// Whether or not the given file name is that of a
+// session directory created by `Session::create`.
+fn is_session_name(name: &std::ffi::OsStr) -> bool {
+    let name = match name.to_str() {
+        Some(name) => name,
+        None => return false,
+    };
+    let is_number = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    match name.split_once('-') {
+        Some((pid, n)) => is_number(pid) && is_number(n),
+        None => false,
+    }
+}
+
+// Whether or not the given file name is that of a
+// copy created by versions of hotlib prior to
+// session directories, i.e. the library's file
+// stem followed by its slugified RFC 3339 build
+// timestamp, e.g.
+// `libfoo-2019-05-03t12-34-56-000000000z.so`.
+fn is_legacy_name(name: &std::ffi::OsStr) -> bool {
+    let stem = match name.to_str().and_then(|name| name.strip_suffix(std::env::consts::DLL_EXTENSION)) {
+        Some(stem) => stem,
+        None => return false,
+    };
+    let stem = match stem.strip_suffix(".").and_then(|stem| stem.strip_suffix('z')) {
+        Some(stem) => stem,
+        None => return false,
+    };
+    let is_number = |s: &str, len: usize| s.len() == len && s.bytes().all(|b| b.is_ascii_digit());
+    let parts: Vec<&str> = stem.split('-').collect();
+    let is_timestamp = |parts: &[&str]| match *parts {
+        [year, month, day_hour, minute, second, ref fraction @ ..] => {
+            is_number(year, 4)
+                && is_number(month, 2)
+                && day_hour.len() == 5
+                && is_number(&day_hour[..2], 2)
+                && &day_hour[2..3] == "t"
+                && is_number(&day_hour[3..], 2)
+                && is_number(minute, 2)
+                && is_number(second, 2)
+                && fraction.iter().all(|f| !f.is_empty() && f.bytes().all(|b| b.is_ascii_digit()))
+        }
+        _ => false,
+    };
+    // The timestamp follows a non-empty stem, with or
+    // without fractional seconds.
+    [6, 5].iter().any(|&len| {
+        parts.len() > len
+            && !parts[..parts.len() - len].concat().is_empty()
+            && is_timestamp(&parts[parts.len() - len..])
+    })
+}
+
+// The session directories owned by this process.
+fn session_dirs() -> Vec<PathBuf> {
+    let sessions = SESSIONS.lock().expect("sessions lock poisoned");
+    sessions
+        .iter()
+        .flat_map(|sessions| sessions.values())
+        .map(|session| session.dir.clone())
+        .collect()
+}
+
+// Create the directory if necessary, checking that
+// it is a directory owned by and only accessible to
+// the current user.
+fn create_private_dir(dir: &Path) -> Result<(), LoadError> {
+    let mut builder = std::fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::DirBuilderExt;
+        builder.mode(0o700);
+    }
+    builder.create(dir)?;
+
+    let metadata = dir.symlink_metadata()?;
+    let insecure = || LoadError::InsecureTmpDir { path: dir.to_path_buf() };
+    if !metadata.is_dir() {
+        return Err(insecure());
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let uid = unsafe { libc::getuid() };
+        if metadata.uid() != uid || metadata.mode() & 0o077 != 0 {
+            return Err(insecure());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn session_names() {
+        assert!(is_session_name("1234-0".as_ref()));
+        assert!(is_session_name("1-12".as_ref()));
+        assert!(!is_session_name("1234".as_ref()));
+        assert!(!is_session_name("1234-".as_ref()));
+        assert!(!is_session_name("-0".as_ref()));
+        assert!(!is_session_name("data-0".as_ref()));
+        assert!(!is_session_name("1234-0-1".as_ref()));
+    }
+
+    #[test]
+    fn legacy_names() {
+        let name = |stem: &str| format!("{}.{}", stem, std::env::consts::DLL_EXTENSION);
+        assert!(is_legacy_name(name("libfoo-2019-05-03t12-34-56-123456789z").as_ref()));
+        assert!(is_legacy_name(name("libfoo-bar-2019-05-03t12-34-56z").as_ref()));
+        assert!(!is_legacy_name(name("2019-05-03t12-34-56-123456789z").as_ref()));
+        assert!(!is_legacy_name(name("libfoo-2019-05-03t12-34-56-123456789").as_ref()));
+        assert!(!is_legacy_name(name("libfoo-0123456789abcdef-1234-0").as_ref()));
+        assert!(!is_legacy_name(name("libfoo").as_ref()));
+        assert!(!is_legacy_name("libfoo-2019-05-03t12-34-56-123456789z.txt".as_ref()));
+    }
+
+    #[test]
+    fn cleanup_removes_legacy_copies() {
+        let dir = std::env::temp_dir().join(format!("hotlib-legacy-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let ext = std::env::consts::DLL_EXTENSION;
+        let legacy = dir.join(format!("libfoo-2019-05-03t12-34-56-123456789z.{}", ext));
+        let foreign = dir.join(format!("libfoo.{}", ext));
+        for path in [&legacy, &foreign] {
+            std::fs::write(path, "").unwrap();
+        }
+
+        let removed = cleanup_legacy_dir(&dir).unwrap();
+        let legacy_exists = legacy.exists();
+        let foreign_exists = foreign.exists();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(removed, 1);
+        assert!(!legacy_exists);
+        assert!(foreign_exists);
+    }
+
+    #[test]
+    fn cleanup_only_removes_stale_sessions() {
+        let dir = std::env::temp_dir().join(format!("hotlib-tmp-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let stale = dir.join("1-0");
+        let foreign = dir.join("data");
+        for path in [&stale, &foreign] {
+            std::fs::create_dir_all(path).unwrap();
+            std::fs::write(path.join(LOCK_FILE_NAME), "").unwrap();
+            std::fs::write(path.join("file"), "").unwrap();
+        }
+
+        let removed = cleanup_dir(&dir).unwrap();
+        let stale_exists = stale.exists();
+        let foreign_exists = foreign.join("file").exists();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(removed, 1);
+        assert!(!stale_exists);
+        assert!(foreign_exists);
+    }
+}
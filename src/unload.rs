@@ -0,0 +1,141 @@
+//! Verifying that an unloaded library is no longer
+//! mapped into the process.
+//!
+//! `dlclose` does not guarantee that a library is
+//! unmapped. Libraries marked `DF_1_NODELETE`, or
+//! that registered thread-local destructors on
+//! threads that are still running, remain mapped,
+//! so their code keeps running and memory grows
+//! with every reload.
+
+use std::path::Path;
+use std::sync::atomic::{self, AtomicUsize};
+
+/// The result of unloading a `TempLibrary`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnloadOutcome {
+    /// The library is no longer mapped into the
+    /// process.
+    Unloaded,
+    /// The library remains mapped into the process
+    /// after being closed.
+    StillMapped {
+        /// The likely reason the library remains
+        /// mapped.
+        cause: StillMappedCause,
+    },
+    /// Unloading could not be verified on this
+    /// platform.
+    Unverified,
+}
+
+/// The likely reason that an unloaded library
+/// remains mapped into the process.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StillMappedCause {
+    /// The library is marked `DF_1_NODELETE`, e.g.
+    /// as it was linked with `-z nodelete` or
+    /// defines `STB_GNU_UNIQUE` symbols (common in
+    /// C++ libraries).
+    NoDelete,
+    /// The library may have registered thread-local
+    /// destructors (e.g. via `thread_local!`) on
+    /// threads that are still running. The library
+    /// remains mapped until those threads exit.
+    ThreadLocalDestructors,
+    /// No likely cause could be determined, e.g.
+    /// another handle to the library remains open
+    /// or a library depending upon it remains
+    /// loaded.
+    Unknown,
+}
+
+// The number of generations that remained mapped
+// after being unloaded.
+static LEAKED: AtomicUsize = AtomicUsize::new(0);
+
+/// The number of libraries that remained mapped
+/// into the process after being unloaded by this
+/// process.
+pub fn leaked_generations() -> usize {
+    LEAKED.load(atomic::Ordering::SeqCst)
+}
+
+/// Check whether or not the library loaded from
+/// `path` (or the given memfd) is still mapped into
+/// the process, inspecting the library to determine
+/// the likely cause if so.
+///
+/// This should be called after the library is
+/// closed, but before its temporary copy is
+/// removed.
+#[cfg(target_os = "linux")]
+pub(crate) fn verify(path: &Path, memfd: Option<&std::fs::File>) -> UnloadOutcome {
+    let maps = match std::fs::read_to_string("/proc/self/maps") {
+        Ok(maps) => maps,
+        Err(_) => return UnloadOutcome::Unverified,
+    };
+    let name = path.to_string_lossy();
+    let deleted = format!("{} (deleted)", name);
+    let mapped = maps
+        .lines()
+        .any(|line| line.ends_with(&*name) || line.ends_with(&deleted));
+    if !mapped {
+        return UnloadOutcome::Unloaded;
+    }
+    LEAKED.fetch_add(1, atomic::Ordering::SeqCst);
+    let cause = match memfd {
+        Some(memfd) => {
+            use std::os::unix::io::AsRawFd;
+            let fd_path = format!("/proc/self/fd/{}", memfd.as_raw_fd());
+            still_mapped_cause(Path::new(&fd_path))
+        }
+        None => still_mapped_cause(path),
+    };
+    tracing::warn!("{:?} remains mapped after being unloaded: {:?}", path, cause);
+    UnloadOutcome::StillMapped { cause }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn verify(_path: &Path, _memfd: Option<&std::fs::File>) -> UnloadOutcome {
+    UnloadOutcome::Unverified
+}
+
+// Inspect the library for the likely reason that
+// it remains mapped.
+#[cfg(target_os = "linux")]
+fn still_mapped_cause(elf_path: &Path) -> StillMappedCause {
+    use goblin::elf::dynamic::DF_1_NODELETE;
+    use goblin::elf::section_header::SHN_UNDEF;
+    use goblin::elf::sym::STB_GNU_UNIQUE;
+
+    let bytes = match std::fs::read(elf_path) {
+        Ok(bytes) => bytes,
+        Err(_) => return StillMappedCause::Unknown,
+    };
+    let elf = match goblin::elf::Elf::parse(&bytes) {
+        Ok(elf) => elf,
+        Err(_) => return StillMappedCause::Unknown,
+    };
+
+    let flags_1 = elf.dynamic.as_ref().map(|dynamic| dynamic.info.flags_1).unwrap_or(0);
+    let unique = elf.dynsyms.iter().any(|sym| sym.st_bind() == STB_GNU_UNIQUE);
+    if flags_1 & DF_1_NODELETE != 0 || unique {
+        return StillMappedCause::NoDelete;
+    }
+
+    // Thread-local destructors are registered with
+    // the C runtime, which keeps the library mapped
+    // until they run.
+    let thread_atexit = elf
+        .dynsyms
+        .iter()
+        .filter(|sym| sym.st_shndx == SHN_UNDEF as usize)
+        .filter_map(|sym| elf.dynstrtab.get_at(sym.st_name))
+        .any(|name| name == "__cxa_thread_atexit_impl" || name == "__cxa_thread_atexit");
+    if thread_atexit {
+        return StillMappedCause::ThreadLocalDestructors;
+    }
+
+    StillMappedCause::Unknown
+}
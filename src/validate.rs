@@ -0,0 +1,348 @@
+//! Checking that a dynamic library may be loaded
+//! into this process before handing it to the
+//! dynamic linker.
+//!
+//! The dynamic linker reports problems via a terse
+//! `dlerror` string, or with lazy binding, not at
+//! all until an unresolved function is first
+//! called. Inspecting the ELF headers, `DT_NEEDED`
+//! entries and dynamic symbol table up front allows
+//! for reporting these problems as a `LoadError`.
+
+use crate::object::Object;
+use crate::LoadError;
+use std::path::Path;
+
+/// Check that the library at the given path was
+/// built for the same architecture as this process.
+#[cfg(target_os = "linux")]
+pub(crate) fn check_arch(dylib_path: &Path, object: &Object) -> Result<(), LoadError> {
+    let found = match object.elf().and_then(|elf| Header::new(&elf.header)) {
+        None => return Ok(()),
+        Some(header) => header,
+    };
+    let expected = match host_header() {
+        None => return Ok(()),
+        Some(header) => header,
+    };
+    if found == expected {
+        return Ok(());
+    }
+    Err(LoadError::ArchMismatch {
+        path:     dylib_path.to_path_buf(),
+        expected: expected.to_string(),
+        found:    found.to_string(),
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn check_arch(_dylib_path: &Path, _object: &Object) -> Result<(), LoadError> {
+    Ok(())
+}
+
+/// Check that every `DT_NEEDED` entry of the
+/// given library may be found and that every
+/// undefined symbol it references may be resolved.
+///
+/// This should be called once the library's staged
+/// dependencies have been loaded. Dependencies are
+/// never opened, so as not to run their
+/// constructors. Those that are already loaded are
+/// searched via the dynamic linker, while others
+/// are located and their symbol tables read.
+#[cfg(target_os = "linux")]
+pub(crate) fn check_linkage(dylib_path: &Path, object: &Object) -> Result<(), LoadError> {
+    use goblin::elf::section_header::SHN_UNDEF;
+    use goblin::elf::sym::STB_GLOBAL;
+
+    let elf = match object.elf() {
+        None => return Ok(()),
+        Some(elf) => elf,
+    };
+
+    let runpath = runpath_dirs(elf);
+    let mut scope = Scope::default();
+    let missing: Vec<String> = elf
+        .libraries
+        .iter()
+        .filter(|name| !scope.add(name, &runpath))
+        .map(|name| name.to_string())
+        .collect();
+    if !missing.is_empty() {
+        return Err(LoadError::MissingDependencies {
+            path:  dylib_path.to_path_buf(),
+            names: missing,
+        });
+    }
+
+    // Weak undefined symbols may legitimately
+    // remain unresolved.
+    let mut unresolved: Vec<String> = elf
+        .dynsyms
+        .iter()
+        .filter(|sym| sym.st_shndx == SHN_UNDEF as usize && sym.st_bind() == STB_GLOBAL)
+        .filter_map(|sym| elf.dynstrtab.get_at(sym.st_name))
+        .filter(|name| !name.is_empty() && !scope.contains(name))
+        .map(|name| name.to_string())
+        .collect();
+    if unresolved.is_empty() {
+        return Ok(());
+    }
+    unresolved.sort();
+    unresolved.dedup();
+    Err(LoadError::UnresolvedSymbols {
+        path:    dylib_path.to_path_buf(),
+        symbols: unresolved,
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn check_linkage(_dylib_path: &Path, _object: &Object) -> Result<(), LoadError> {
+    Ok(())
+}
+
+// The parts of an ELF header that determine
+// whether or not a library may be loaded into a
+// process.
+#[cfg(target_os = "linux")]
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct Header {
+    machine:       u16,
+    is_64:         bool,
+    little_endian: bool,
+}
+
+// The dependencies against which a library's
+// undefined symbols are resolved.
+#[cfg(target_os = "linux")]
+#[derive(Default)]
+struct Scope {
+    // Dependencies that are already loaded.
+    handles: Vec<Handle>,
+    // The symbols defined by dependencies that are
+    // not loaded, read from their files.
+    symbols: std::collections::HashSet<String>,
+    // The names of the dependencies added so far.
+    names:   std::collections::HashSet<String>,
+}
+
+#[cfg(target_os = "linux")]
+impl Scope {
+
+    // Add the dependency with the given name along
+    // with its own dependencies, searching the given
+    // runpath first.
+    //
+    // Returns `false` if the dependency could not be
+    // found.
+    fn add(&mut self, name: &str, runpath: &[&str]) -> bool {
+        use goblin::elf::section_header::SHN_UNDEF;
+
+        if !self.names.insert(name.to_string()) {
+            return true;
+        }
+        // Searching a loaded library via the dynamic
+        // linker also searches its dependencies.
+        if let Some(handle) = Handle::open_loaded(name) {
+            self.handles.push(handle);
+            return true;
+        }
+        let path = match locate(name, runpath) {
+            None => return false,
+            Some(path) => path,
+        };
+        let bytes = match std::fs::read(&path) {
+            Ok(bytes) => bytes,
+            Err(_) => return false,
+        };
+        let elf = match goblin::elf::Elf::parse(&bytes) {
+            Ok(elf) => elf,
+            Err(_) => return false,
+        };
+        let defined = elf
+            .dynsyms
+            .iter()
+            .filter(|sym| sym.st_shndx != SHN_UNDEF as usize)
+            .filter_map(|sym| elf.dynstrtab.get_at(sym.st_name))
+            .filter(|name| !name.is_empty())
+            .map(|name| name.to_string());
+        self.symbols.extend(defined);
+
+        // Dependencies of dependencies that cannot be
+        // found are left for the dynamic linker to
+        // report.
+        let runpath = runpath_dirs(&elf);
+        for name in &elf.libraries {
+            self.add(name, &runpath);
+        }
+        true
+    }
+
+    // Whether or not the symbol with the given name
+    // may be resolved by the dynamic linker.
+    fn contains(&self, name: &str) -> bool {
+        if self.symbols.contains(name) {
+            return true;
+        }
+        let name = match std::ffi::CString::new(name) {
+            Ok(name) => name,
+            Err(_) => return false,
+        };
+        let default = unsafe { libc::dlsym(libc::RTLD_DEFAULT, name.as_ptr()) };
+        !default.is_null() || self.handles.iter().any(|handle| handle.contains(&name))
+    }
+}
+
+// A handle to a loaded dependency, released on
+// `Drop`.
+#[cfg(target_os = "linux")]
+struct Handle(*mut libc::c_void);
+
+#[cfg(target_os = "linux")]
+impl Handle {
+
+    // A handle to the dependency with the given name
+    // if it is already loaded into the process.
+    fn open_loaded(name: &str) -> Option<Self> {
+        let name = std::ffi::CString::new(name).ok()?;
+        let flags = libc::RTLD_LAZY | libc::RTLD_NOLOAD;
+        let handle = unsafe { libc::dlopen(name.as_ptr(), flags) };
+        match handle.is_null() {
+            true => None,
+            false => Some(Handle(handle)),
+        }
+    }
+
+    // Whether or not the symbol with the given name
+    // may be resolved via this dependency.
+    fn contains(&self, name: &std::ffi::CStr) -> bool {
+        !unsafe { libc::dlsym(self.0, name.as_ptr()) }.is_null()
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for Handle {
+    fn drop(&mut self) {
+        unsafe { libc::dlclose(self.0) };
+    }
+}
+
+// The runpath of the given library, excluding
+// entries relative to `$ORIGIN`, which no longer
+// resolve from its temporary copy.
+#[cfg(target_os = "linux")]
+fn runpath_dirs<'a>(elf: &goblin::elf::Elf<'a>) -> Vec<&'a str> {
+    elf.runpaths
+        .iter()
+        .chain(&elf.rpaths)
+        .flat_map(|paths| paths.split(':'))
+        .filter(|dir| !dir.is_empty() && !dir.contains("ORIGIN"))
+        .collect()
+}
+
+// Locate the dependency with the given name as the
+// dynamic linker would: via the given runpath,
+// `LD_LIBRARY_PATH`, the `ld.so` cache and finally
+// the default system directories.
+#[cfg(target_os = "linux")]
+fn locate(name: &str, runpath: &[&str]) -> Option<std::path::PathBuf> {
+    if name.contains('/') {
+        let path = Path::new(name);
+        return path.is_file().then(|| path.to_path_buf());
+    }
+    let library_path = std::env::var("LD_LIBRARY_PATH").unwrap_or_default();
+    let is_host = |path: &Path| {
+        host_header().is_none_or(|host| read_header(path).ok().flatten() == Some(host))
+    };
+    runpath
+        .iter()
+        .copied()
+        .chain(library_path.split(':').filter(|dir| !dir.is_empty()))
+        .map(|dir| Path::new(dir).join(name))
+        .chain(ld_so_cache().iter().filter(|(soname, _)| soname == name).map(|(_, path)| path.clone()))
+        .chain(DEFAULT_DIRS.iter().map(|dir| Path::new(dir).join(name)))
+        .find(|path| path.is_file() && is_host(path))
+}
+
+// The directories searched by the dynamic linker
+// after those within its cache.
+#[cfg(target_os = "linux")]
+const DEFAULT_DIRS: &[&str] = &["/lib64", "/usr/lib64", "/lib", "/usr/lib"];
+
+// The sonames and paths of the libraries within the
+// `ld.so` cache, as reported by `ldconfig -p`.
+//
+// Only queried if a dependency is not already
+// loaded, and cached for the life of the process.
+#[cfg(target_os = "linux")]
+fn ld_so_cache() -> &'static [(String, std::path::PathBuf)] {
+    static CACHE: std::sync::OnceLock<Vec<(String, std::path::PathBuf)>> = std::sync::OnceLock::new();
+    CACHE.get_or_init(|| {
+        let output = ["ldconfig", "/sbin/ldconfig", "/usr/sbin/ldconfig"]
+            .iter()
+            .find_map(|ldconfig| std::process::Command::new(ldconfig).arg("-p").output().ok())
+            .filter(|output| output.status.success());
+        let stdout = match output {
+            None => return vec![],
+            Some(output) => String::from_utf8_lossy(&output.stdout).into_owned(),
+        };
+        // Entries are of the form
+        // `\tlibm.so.6 (libc6,x86-64) => /lib/.../libm.so.6`.
+        stdout
+            .lines()
+            .filter_map(|line| {
+                let (soname, rest) = line.trim().split_once(' ')?;
+                let (_, path) = rest.split_once(" => ")?;
+                Some((soname.to_string(), std::path::PathBuf::from(path)))
+            })
+            .collect()
+    })
+}
+
+#[cfg(target_os = "linux")]
+impl Header {
+
+    // The parts of the given ELF header that
+    // determine compatibility, or `None` if they are
+    // invalid.
+    fn new(header: &goblin::elf::header::Header) -> Option<Self> {
+        Some(Header {
+            machine:       header.e_machine,
+            is_64:         header.container().ok()?.is_big(),
+            little_endian: header.endianness().ok()?.is_little(),
+        })
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl std::fmt::Display for Header {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let machine = goblin::elf::header::machine_to_str(self.machine);
+        let bits = if self.is_64 { 64 } else { 32 };
+        let endian = if self.little_endian { "little" } else { "big" };
+        write!(f, "{} ({}-bit, {}-endian)", machine, bits, endian)
+    }
+}
+
+// Read the header of the ELF file at the given
+// path, returning `None` if it is not an ELF file.
+#[cfg(target_os = "linux")]
+fn read_header(path: &Path) -> std::io::Result<Option<Header>> {
+    use std::io::Read;
+    let mut bytes = vec![];
+    std::fs::File::open(path)?
+        .take(goblin::elf::header::header64::SIZEOF_EHDR as u64)
+        .read_to_end(&mut bytes)?;
+    match goblin::elf::Elf::parse_header(&bytes) {
+        Ok(header) => Ok(Header::new(&header)),
+        Err(_) => Ok(None),
+    }
+}
+
+// The header of this process' executable, cached
+// for the life of the process.
+#[cfg(target_os = "linux")]
+fn host_header() -> Option<Header> {
+    static HOST: std::sync::OnceLock<Option<Header>> = std::sync::OnceLock::new();
+    *HOST.get_or_init(|| read_header(Path::new("/proc/self/exe")).ok().flatten())
+}
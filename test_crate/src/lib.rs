@@ -1,4 +1,7 @@
 #[no_mangle]
+pub static HOTLIB_ABI_VERSION: u32 = 1;
+
+#[hotlib_plugin::export]
 pub fn foo(a: i32, b: i32) -> i32 {
     a * b
 }